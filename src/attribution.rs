@@ -0,0 +1,443 @@
+use std::collections::{
+  BTreeMap,
+  BTreeSet,
+  HashMap,
+  HashSet,
+  VecDeque,
+};
+
+use anyhow::{
+  Context as _,
+  Error,
+  Result,
+};
+use itertools::{
+  EitherOrBoth,
+  Itertools as _,
+};
+use size::Size;
+
+use crate::{
+  ClosureSource,
+  Diff,
+  PathInfo,
+  StorePath,
+};
+
+/// The bytes of the paths only in the old or new closure that a single
+/// system package depends on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackageAttribution {
+  /// The name of the system package, without the hash and version.
+  pub name:      String,
+  /// The bytes of the removed and added paths that no other system package
+  /// depends on.
+  pub exclusive: Diff<Size>,
+  /// The bytes of the removed and added paths that other system packages
+  /// depend on as well.
+  pub shared:    Diff<Size>,
+}
+
+impl PackageAttribution {
+  /// Returns by how much the paths only this package depends on grew or
+  /// shrank.
+  #[must_use]
+  pub fn exclusive_change(&self) -> Size {
+    self.exclusive.new - self.exclusive.old
+  }
+}
+
+/// Attributes the bytes of the paths only in the old or new closure to the
+/// system packages depending on them, answering why a closure grew or
+/// shrank.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SizeAttribution {
+  /// The system packages, ranked by how much their exclusive bytes changed.
+  pub packages:     Vec<PackageAttribution>,
+  /// The bytes of the removed and added paths that several system packages
+  /// depend on, each path counted once.
+  pub shared:       Diff<Size>,
+  /// The bytes of the removed and added paths that no system package depends
+  /// on, like the kernel or `/etc`.
+  pub unattributed: Diff<Size>,
+}
+
+/// The attribution of the paths only in one of the closures.
+#[derive(Default)]
+struct Attribution {
+  /// The exclusive and shared bytes of every system package.
+  packages:     BTreeMap<String, (Size, Size)>,
+  shared:       Size,
+  unattributed: Size,
+}
+
+/// Returns the name of `path` without the hash and version, or its file name
+/// if it can't be parsed.
+fn package_name(path: &StorePath) -> String {
+  match path.parse_name_and_version() {
+    Ok((name, _)) => name.to_owned(),
+    Err(error) => {
+      log::warn!("error parsing system path name and version: {error}");
+      path.to_string_lossy().into_owned()
+    },
+  }
+}
+
+fn paths(infos: &[PathInfo]) -> HashSet<&StorePath> {
+  infos.iter().map(|info| &info.path).collect()
+}
+
+/// Attributes the paths of `infos` that are not in `other` to the `roots`
+/// reaching them through the reference graph.
+fn attribute(
+  infos: &[PathInfo],
+  other: &HashSet<&StorePath>,
+  roots: &[StorePath],
+) -> Attribution {
+  let by_path = infos
+    .iter()
+    .map(|info| (&info.path, info))
+    .collect::<HashMap<_, _>>();
+
+  let mut owners = HashMap::<&StorePath, BTreeSet<String>>::new();
+
+  for root in roots {
+    let name = package_name(root);
+
+    let mut seen = HashSet::from([root]);
+    let mut queue = VecDeque::from([root]);
+
+    while let Some(path) = queue.pop_front() {
+      let Some(info) = by_path.get(path) else {
+        continue;
+      };
+
+      if !other.contains(path) {
+        owners.entry(path).or_default().insert(name.clone());
+      }
+
+      for reference in &info.references {
+        if seen.insert(reference) {
+          queue.push_back(reference);
+        }
+      }
+    }
+  }
+
+  let mut attribution = Attribution::default();
+
+  for info in infos {
+    if other.contains(&info.path) {
+      continue;
+    }
+
+    let names = owners.remove(&info.path).unwrap_or_default();
+    let exclusive = names.len() == 1;
+
+    match names.len() {
+      0 => attribution.unattributed += info.nar_size,
+      1 => {},
+      _ => attribution.shared += info.nar_size,
+    }
+
+    for name in names {
+      let &mut (ref mut exclusive_size, ref mut shared_size) =
+        attribution.packages.entry(name).or_default();
+
+      if exclusive {
+        *exclusive_size += info.nar_size;
+      } else {
+        *shared_size += info.nar_size;
+      }
+    }
+  }
+
+  attribution
+}
+
+impl SizeAttribution {
+  /// Queries the closures of `path_old` and `path_new` from `source_old` and
+  /// `source_new` respectively and attributes the paths only in one of them
+  /// to the system packages of that closure.
+  #[expect(clippy::missing_errors_doc)]
+  pub fn query(
+    source_old: &dyn ClosureSource,
+    path_old: &StorePath,
+    source_new: &dyn ClosureSource,
+    path_new: &StorePath,
+  ) -> Result<Self> {
+    let query = |source: &dyn ClosureSource, path: &StorePath| {
+      let infos = source.query_closure_infos(path).with_context(|| {
+        format!(
+          "failed to query dependencies of path '{path}'",
+          path = path.display()
+        )
+      })?;
+
+      let roots = source
+        .query_system_derivations(path)
+        .with_context(|| {
+          format!(
+            "failed to query system derivations of path '{path}'",
+            path = path.display()
+          )
+        })?
+        .into_iter()
+        // The system path may reference itself, which would make it the
+        // owner of everything.
+        .filter(|root| !root.to_string_lossy().ends_with("-system-path"))
+        .collect::<Vec<_>>();
+
+      Ok::<_, Error>((infos, roots))
+    };
+
+    let (infos_old, roots_old) = query(source_old, path_old)?;
+    let (infos_new, roots_new) = query(source_new, path_new)?;
+
+    Ok(Self::from_closures(
+      Diff {
+        old: &infos_old,
+        new: &infos_new,
+      },
+      Diff {
+        old: &roots_old,
+        new: &roots_new,
+      },
+    ))
+  }
+
+  /// Attributes the paths only in the old or new closure to the system
+  /// packages, given as `roots`, of that closure.
+  #[must_use]
+  pub fn from_closures(
+    infos: Diff<&[PathInfo]>,
+    roots: Diff<&[StorePath]>,
+  ) -> Self {
+    let paths_old = paths(infos.old);
+    let paths_new = paths(infos.new);
+
+    let old = attribute(infos.old, &paths_new, roots.old);
+    let new = attribute(infos.new, &paths_old, roots.new);
+
+    let mut packages = old
+      .packages
+      .into_iter()
+      .merge_join_by(new.packages, |this, that| this.0.cmp(&that.0))
+      .map(|entry| {
+        let (name, (exclusive_old, shared_old), (exclusive_new, shared_new)) =
+          match entry {
+            EitherOrBoth::Both((name, old), (_, new)) => (name, old, new),
+            EitherOrBoth::Left((name, old)) => (name, old, Default::default()),
+            EitherOrBoth::Right((name, new)) => (name, Default::default(), new),
+          };
+
+        PackageAttribution {
+          name,
+          exclusive: Diff {
+            old: exclusive_old,
+            new: exclusive_new,
+          },
+          shared: Diff {
+            old: shared_old,
+            new: shared_new,
+          },
+        }
+      })
+      .collect::<Vec<_>>();
+
+    packages.sort_by(|this, that| {
+      let impact = |package: &PackageAttribution| {
+        (
+          package.exclusive_change().bytes().abs(),
+          (package.shared.new - package.shared.old).bytes().abs(),
+        )
+      };
+
+      impact(that)
+        .cmp(&impact(this))
+        .then_with(|| this.name.cmp(&that.name))
+    });
+
+    Self {
+      packages,
+      shared: Diff {
+        old: old.shared,
+        new: new.shared,
+      },
+      unattributed: Diff {
+        old: old.unattributed,
+        new: new.unattributed,
+      },
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use size::Size;
+
+  use super::{
+    PackageAttribution,
+    SizeAttribution,
+  };
+  use crate::{
+    Diff,
+    PathInfo,
+    StorePath,
+    test_utils::store_path,
+  };
+
+  fn info(
+    path: &StorePath,
+    references: &[&StorePath],
+    nar_size: u64,
+  ) -> PathInfo {
+    PathInfo {
+      path:       path.clone(),
+      references: references.iter().copied().cloned().collect(),
+      nar_size:   Size::from_bytes(nar_size),
+      deriver:    None,
+    }
+  }
+
+  fn sizes(old: u64, new: u64) -> Diff<Size> {
+    Diff {
+      old: Size::from_bytes(old),
+      new: Size::from_bytes(new),
+    }
+  }
+
+  #[test]
+  fn attribution_exclusive() {
+    let firefox_old = store_path("firefox-120.0");
+    let firefox_new = store_path("firefox-121.0");
+    let gtk_old = store_path("gtk-3.0");
+    let gtk_new = store_path("gtk-3.1");
+    let glibc = store_path("glibc-2.40");
+
+    let old = [
+      info(&firefox_old, &[&gtk_old, &glibc], 100),
+      info(&gtk_old, &[], 1000),
+      info(&glibc, &[], 5),
+    ];
+    let new = [
+      info(&firefox_new, &[&gtk_new, &glibc], 200),
+      info(&gtk_new, &[], 2000),
+      info(&glibc, &[], 5),
+    ];
+
+    let attribution = SizeAttribution::from_closures(
+      Diff {
+        old: &old,
+        new: &new,
+      },
+      Diff {
+        old: &[firefox_old],
+        new: &[firefox_new],
+      },
+    );
+
+    // The paths in both closures are left out.
+    assert_eq!(attribution, SizeAttribution {
+      packages:     vec![PackageAttribution {
+        name:      "firefox".to_owned(),
+        exclusive: sizes(1100, 2200),
+        shared:    sizes(0, 0),
+      }],
+      shared:       sizes(0, 0),
+      unattributed: sizes(0, 0),
+    });
+  }
+
+  #[test]
+  fn attribution_shared() {
+    let firefox = store_path("firefox-121.0");
+    let nano = store_path("nano-7.2");
+    let ncurses = store_path("ncurses-6.4");
+
+    let new = [
+      info(&firefox, &[&ncurses], 200),
+      info(&nano, &[&ncurses], 30),
+      info(&ncurses, &[], 20000),
+    ];
+
+    let attribution = SizeAttribution::from_closures(
+      Diff {
+        old: &[],
+        new: &new,
+      },
+      Diff {
+        old: &[],
+        new: &[firefox, nano],
+      },
+    );
+
+    assert_eq!(attribution, SizeAttribution {
+      packages:     vec![
+        PackageAttribution {
+          name:      "firefox".to_owned(),
+          exclusive: sizes(0, 200),
+          shared:    sizes(0, 20000),
+        },
+        PackageAttribution {
+          name:      "nano".to_owned(),
+          exclusive: sizes(0, 30),
+          shared:    sizes(0, 20000),
+        },
+      ],
+      shared:       sizes(0, 20000),
+      unattributed: sizes(0, 0),
+    });
+  }
+
+  #[test]
+  fn attribution_unattributed() {
+    let vim = store_path("vim-9.0");
+    let etc = store_path("etc");
+
+    let old = [info(&vim, &[], 10), info(&etc, &[], 7)];
+
+    let attribution = SizeAttribution::from_closures(
+      Diff {
+        old: &old,
+        new: &[],
+      },
+      Diff {
+        old: &[vim],
+        new: &[],
+      },
+    );
+
+    assert_eq!(attribution.unattributed, sizes(7, 0));
+    assert_eq!(attribution.packages[0].exclusive, sizes(10, 0));
+  }
+
+  #[test]
+  fn attribution_ranks_by_exclusive_change() {
+    let vim = store_path("vim-9.0");
+    let nano = store_path("nano-7.2");
+    let bash = store_path("bash-5.2");
+
+    let old = [info(&vim, &[], 100), info(&bash, &[], 1)];
+    let new = [info(&nano, &[], 30)];
+
+    let attribution = SizeAttribution::from_closures(
+      Diff {
+        old: &old,
+        new: &new,
+      },
+      Diff {
+        old: &[vim, bash],
+        new: &[nano],
+      },
+    );
+
+    let names = attribution
+      .packages
+      .iter()
+      .map(|package| package.name.as_str())
+      .collect::<Vec<_>>();
+
+    assert_eq!(names, ["vim", "nano", "bash"]);
+  }
+}
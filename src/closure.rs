@@ -0,0 +1,427 @@
+use std::{
+  collections::{
+    BTreeMap,
+    HashMap,
+    HashSet,
+    VecDeque,
+  },
+  fs,
+  path::Path,
+  sync::Arc,
+};
+
+use anyhow::{
+  Context as _,
+  Result,
+  anyhow,
+  bail,
+};
+use itertools::Itertools as _;
+use size::Size;
+
+use crate::StorePath;
+
+/// The information a closure source knows about a single store path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathInfo {
+  /// The store path itself.
+  pub path:       StorePath,
+  /// The store paths this path references at runtime.
+  pub references: Vec<StorePath>,
+  /// The size of the NAR serialisation of this path.
+  pub nar_size:   Size,
+  /// The derivation that produced this path, if known.
+  pub deriver:    Option<StorePath>,
+}
+
+/// A source of closure information, such as the Nix database.
+///
+/// Only [`ClosureSource::query_path_info`] has to be implemented, every other
+/// query walks the reference graph using it. Sources that can answer a query
+/// more efficiently should override it.
+pub trait ClosureSource {
+  /// Gets the information about a single store path.
+  fn query_path_info(&self, path: &StorePath) -> Result<PathInfo>;
+
+  /// Gets the information about every path in the closure of `path`,
+  /// including `path` itself.
+  fn query_closure_infos(&self, path: &StorePath) -> Result<Vec<PathInfo>> {
+    let mut seen = HashSet::from([path.clone()]);
+    let mut queue = VecDeque::from([path.clone()]);
+    let mut infos = Vec::new();
+
+    while let Some(path) = queue.pop_front() {
+      let info = self.query_path_info(&path).with_context(|| {
+        format!(
+          "failed to query path info of '{path}'",
+          path = path.display(),
+        )
+      })?;
+
+      for reference in &info.references {
+        if seen.insert(reference.clone()) {
+          queue.push_back(reference.clone());
+        }
+      }
+
+      infos.push(info);
+    }
+
+    Ok(infos)
+  }
+
+  /// Gathers all paths in the closure of `path`, including `path` itself.
+  fn query_closure(&self, path: &StorePath) -> Result<Vec<StorePath>> {
+    Ok(
+      self
+        .query_closure_infos(path)?
+        .into_iter()
+        .map(|info| info.path)
+        .collect(),
+    )
+  }
+
+  /// Gets the NAR size of every path in the closure of `path`, including
+  /// `path` itself.
+  fn query_closure_nar_sizes(
+    &self,
+    path: &StorePath,
+  ) -> Result<Vec<(StorePath, Size)>> {
+    Ok(
+      self
+        .query_closure_infos(path)?
+        .into_iter()
+        .map(|info| (info.path, info.nar_size))
+        .collect(),
+    )
+  }
+
+  /// Gets the total closure size of `path` by summing up the NAR size of all
+  /// paths in its closure.
+  fn query_closure_size(&self, path: &StorePath) -> Result<Size> {
+    Ok(
+      self
+        .query_closure_infos(path)?
+        .iter()
+        .fold(Size::from_bytes(0), |sum, info| sum + info.nar_size),
+    )
+  }
+
+  /// Gets the paths that are directly included in the system path of
+  /// `system`.
+  ///
+  /// Will return nothing for non-system paths.
+  fn query_system_derivations(
+    &self,
+    system: &StorePath,
+  ) -> Result<Vec<StorePath>> {
+    let mut derivations = Vec::new();
+
+    for reference in self.query_path_info(system)?.references {
+      if !reference.to_string_lossy().ends_with("-system-path") {
+        continue;
+      }
+
+      derivations.extend(self.query_path_info(&reference)?.references);
+    }
+
+    Ok(derivations)
+  }
+
+  /// Gets the output paths of the derivation at `path` by output name, with
+  /// `None` for outputs that are not built.
+  ///
+  /// Sources that don't know about derivations fail.
+  fn query_derivation_outputs(
+    &self,
+    path: &StorePath,
+  ) -> Result<BTreeMap<String, Option<StorePath>>> {
+    bail!(
+      "failed to query outputs of derivation '{path}', the source does not \
+       know about derivations",
+      path = path.display(),
+    )
+  }
+}
+
+impl<S: ClosureSource + ?Sized> ClosureSource for Arc<S> {
+  fn query_path_info(&self, path: &StorePath) -> Result<PathInfo> {
+    (**self).query_path_info(path)
+  }
+
+  fn query_closure_infos(&self, path: &StorePath) -> Result<Vec<PathInfo>> {
+    (**self).query_closure_infos(path)
+  }
+
+  fn query_closure(&self, path: &StorePath) -> Result<Vec<StorePath>> {
+    (**self).query_closure(path)
+  }
+
+  fn query_closure_nar_sizes(
+    &self,
+    path: &StorePath,
+  ) -> Result<Vec<(StorePath, Size)>> {
+    (**self).query_closure_nar_sizes(path)
+  }
+
+  fn query_closure_size(&self, path: &StorePath) -> Result<Size> {
+    (**self).query_closure_size(path)
+  }
+
+  fn query_system_derivations(
+    &self,
+    system: &StorePath,
+  ) -> Result<Vec<StorePath>> {
+    (**self).query_system_derivations(system)
+  }
+
+  fn query_derivation_outputs(
+    &self,
+    path: &StorePath,
+  ) -> Result<BTreeMap<String, Option<StorePath>>> {
+    (**self).query_derivation_outputs(path)
+  }
+}
+
+/// A closure source holding every path info in memory, usually loaded from a
+/// dump of a store that is not available locally.
+#[derive(Debug, Clone, Default)]
+pub struct Dump(HashMap<StorePath, PathInfo>);
+
+impl FromIterator<PathInfo> for Dump {
+  fn from_iter<I: IntoIterator<Item = PathInfo>>(infos: I) -> Self {
+    Self(
+      infos
+        .into_iter()
+        .map(|info| (info.path.clone(), info))
+        .collect(),
+    )
+  }
+}
+
+impl Dump {
+  /// Reads a dump file, detecting its format from its contents.
+  ///
+  /// Both `nix path-info --json` output and registration files are
+  /// supported.
+  pub fn read_file(path: &Path, store_dir: &Path) -> Result<Self> {
+    let contents = fs::read_to_string(path).with_context(|| {
+      format!("failed to read dump '{path}'", path = path.display())
+    })?;
+
+    if contents.trim_start().starts_with(['[', '{']) {
+      Self::from_path_info_json(&contents, store_dir)
+    } else {
+      Self::from_registration(&contents, store_dir)
+    }
+    .with_context(|| {
+      format!("failed to parse dump '{path}'", path = path.display())
+    })
+  }
+
+  /// Returns the paths that are not referenced by any other path in the
+  /// dump, sorted.
+  #[must_use]
+  pub fn roots(&self) -> Vec<StorePath> {
+    let referenced = self
+      .0
+      .values()
+      .flat_map(|info| {
+        info
+          .references
+          .iter()
+          .filter(move |&reference| *reference != info.path)
+      })
+      .collect::<HashSet<_>>();
+
+    let mut roots = self
+      .0
+      .keys()
+      .filter(|path| !referenced.contains(path))
+      .cloned()
+      .collect::<Vec<_>>();
+
+    roots.sort();
+    roots
+  }
+
+  /// Returns the root of the dump named `name`, without the hash and
+  /// version, or the only root if no name is given.
+  ///
+  /// Dumps like the `registration` file of `closureInfo` often have several
+  /// roots, so this fails if more than one root matches.
+  pub fn root(&self, name: Option<&str>) -> Result<StorePath> {
+    let roots = self.roots();
+
+    let matching = roots
+      .iter()
+      .filter(|root| {
+        name.is_none_or(|name| {
+          root
+            .parse_name_and_version()
+            .is_ok_and(|(root_name, _)| root_name == name)
+        })
+      })
+      .cloned()
+      .collect::<Vec<_>>();
+
+    match <[StorePath; 1]>::try_from(matching) {
+      Ok([root]) => Ok(root),
+      Err(matching) => {
+        let roots = roots
+          .iter()
+          .map(|root| format!("'{root}'", root = root.display()))
+          .join(", ");
+
+        match name {
+          Some(name) => {
+            Err(anyhow!(
+              "expected a single root path named '{name}' in dump, found \
+               {count}, the roots are {roots}",
+              count = matching.len(),
+            ))
+          },
+          None => {
+            Err(anyhow!(
+              "expected a single root path in dump, found {count}: {roots}",
+              count = matching.len(),
+            ))
+          },
+        }
+      },
+    }
+  }
+}
+
+impl ClosureSource for Dump {
+  fn query_path_info(&self, path: &StorePath) -> Result<PathInfo> {
+    self.0.get(path).cloned().ok_or_else(|| {
+      anyhow!(
+        "path '{path}' is not contained in the dump",
+        path = path.display(),
+      )
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use size::Size;
+
+  use super::{
+    ClosureSource as _,
+    Dump,
+    PathInfo,
+  };
+  use crate::{
+    StorePath,
+    test_utils::store_path,
+  };
+
+  fn info(
+    path: &StorePath,
+    references: &[&StorePath],
+    nar_size: u64,
+  ) -> PathInfo {
+    PathInfo {
+      path:       path.clone(),
+      references: references.iter().copied().cloned().collect(),
+      nar_size:   Size::from_bytes(nar_size),
+      deriver:    None,
+    }
+  }
+
+  #[test]
+  fn closure_follows_references() {
+    let hello = store_path("hello-2.12");
+    let glibc = store_path("glibc-2.40");
+    let bash = store_path("bash-5.2");
+
+    let dump = Dump::from_iter([
+      info(&hello, &[&hello, &glibc], 1),
+      info(&glibc, &[], 1),
+      info(&bash, &[&glibc], 1),
+    ]);
+
+    let mut closure = dump.query_closure(&hello).unwrap();
+    closure.sort();
+
+    assert_eq!(closure, [glibc, hello]);
+  }
+
+  #[test]
+  fn closure_size_counts_paths_once() {
+    let hello = store_path("hello-2.12");
+    let bash = store_path("bash-5.2");
+    let glibc = store_path("glibc-2.40");
+
+    let dump = Dump::from_iter([
+      info(&hello, &[&bash, &glibc], 1),
+      info(&bash, &[&glibc], 10),
+      info(&glibc, &[], 100),
+    ]);
+
+    assert_eq!(
+      dump.query_closure_size(&hello).unwrap(),
+      Size::from_bytes(111_u64),
+    );
+  }
+
+  #[test]
+  fn closure_of_unknown_path() {
+    let hello = store_path("hello-2.12");
+    let glibc = store_path("glibc-2.40");
+
+    let dump = Dump::from_iter([info(&hello, &[&glibc], 1)]);
+
+    dump.query_closure(&hello).unwrap_err();
+  }
+
+  #[test]
+  fn system_derivations() {
+    let system = store_path("nixos-system");
+    let system_path = store_path("system-path");
+    let etc = store_path("etc");
+    let hello = store_path("hello-2.12");
+
+    let dump = Dump::from_iter([
+      info(&system, &[&system_path, &etc], 1),
+      info(&system_path, &[&hello], 1),
+      info(&etc, &[&system_path], 1),
+      info(&hello, &[], 1),
+    ]);
+
+    assert_eq!(dump.query_system_derivations(&hello).unwrap(), []);
+    assert_eq!(dump.query_system_derivations(&system).unwrap(), [hello]);
+  }
+
+  #[test]
+  fn dump_roots() {
+    let hello = store_path("hello-2.12");
+    let glibc = store_path("glibc-2.40");
+    let bash = store_path("bash-5.2");
+
+    // A path referencing itself is still a root.
+    let dump = Dump::from_iter([
+      info(&hello, &[&hello, &glibc], 1),
+      info(&glibc, &[], 1),
+      info(&bash, &[&bash], 1),
+    ]);
+
+    assert_eq!(dump.roots(), [bash.clone(), hello.clone()]);
+    assert_eq!(dump.root(Some("hello")).unwrap(), hello);
+    dump.root(Some("glibc")).unwrap_err();
+    dump.root(None).unwrap_err();
+  }
+
+  #[test]
+  fn dump_single_root() {
+    let hello = store_path("hello-2.12");
+    let glibc = store_path("glibc-2.40");
+
+    let dump =
+      Dump::from_iter([info(&hello, &[&glibc], 1), info(&glibc, &[], 1)]);
+
+    assert_eq!(dump.root(None).unwrap(), hello);
+  }
+}
@@ -0,0 +1,623 @@
+use std::{
+  cell::RefCell,
+  collections::{
+    BTreeMap,
+    HashSet,
+  },
+  io::{
+    self,
+    BufReader,
+    BufWriter,
+    Read as _,
+    Write as _,
+  },
+  iter,
+  os::unix::net::UnixStream,
+  path::{
+    Path,
+    PathBuf,
+  },
+};
+
+use anyhow::{
+  Context as _,
+  Result,
+  bail,
+};
+use size::Size;
+
+use crate::{
+  ClosureSource,
+  PathInfo,
+  StorePath,
+};
+
+const WORKER_MAGIC_1: u64 = 0x6E69_7863;
+const WORKER_MAGIC_2: u64 = 0x6478_696F;
+
+/// The protocol version we speak, 1.35.
+const PROTOCOL_VERSION: u64 = 0x123;
+/// The oldest protocol version that sends path infos the way we expect them.
+const MINIMUM_PROTOCOL_VERSION: u64 = 0x111;
+
+const STDERR_NEXT: u64 = 0x6F6C_6D67;
+const STDERR_LAST: u64 = 0x616C_7473;
+const STDERR_ERROR: u64 = 0x6378_7470;
+const STDERR_START_ACTIVITY: u64 = 0x5354_5254;
+const STDERR_STOP_ACTIVITY: u64 = 0x5354_4F50;
+const STDERR_RESULT: u64 = 0x5253_4C54;
+
+const OP_QUERY_PATH_INFO: u64 = 26;
+const OP_QUERY_VALID_PATHS: u64 = 31;
+const OP_QUERY_DERIVATION_OUTPUT_MAP: u64 = 41;
+
+struct Stream {
+  reader: BufReader<UnixStream>,
+  writer: BufWriter<UnixStream>,
+}
+
+impl Stream {
+  fn read_u64(&mut self) -> io::Result<u64> {
+    let mut buffer = [0; 8];
+    self.reader.read_exact(&mut buffer)?;
+    Ok(u64::from_le_bytes(buffer))
+  }
+
+  fn read_bytes(&mut self) -> io::Result<Vec<u8>> {
+    let len = usize::try_from(self.read_u64()?)
+      .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+
+    let mut buffer = vec![0; len];
+    self.reader.read_exact(&mut buffer)?;
+
+    // Strings are padded to a multiple of 8 bytes.
+    let mut padding = [0; 8];
+    self.reader.read_exact(&mut padding[..(8 - len % 8) % 8])?;
+
+    Ok(buffer)
+  }
+
+  fn read_string(&mut self) -> io::Result<String> {
+    String::from_utf8(self.read_bytes()?)
+      .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+  }
+
+  fn read_strings(&mut self) -> io::Result<Vec<String>> {
+    let count = usize::try_from(self.read_u64()?)
+      .map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))?;
+
+    iter::repeat_with(|| self.read_string())
+      .take(count)
+      .collect()
+  }
+
+  fn write_u64(&mut self, value: u64) -> io::Result<()> {
+    self.writer.write_all(&value.to_le_bytes())
+  }
+
+  fn write_string(&mut self, string: &str) -> io::Result<()> {
+    self.write_u64(string.len() as u64)?;
+    self.writer.write_all(string.as_bytes())?;
+    self.writer.write_all(&[0; 8][..(8 - string.len() % 8) % 8])
+  }
+
+  fn write_strings(&mut self, strings: &[&str]) -> io::Result<()> {
+    self.write_u64(strings.len() as u64)?;
+
+    for string in strings {
+      self.write_string(string)?;
+    }
+
+    Ok(())
+  }
+
+  /// Skips the fields of an activity or result message.
+  fn skip_fields(&mut self) -> Result<()> {
+    for _ in 0..self.read_u64()? {
+      match self.read_u64()? {
+        0 => {
+          self.read_u64()?;
+        },
+        1 => {
+          self.read_bytes()?;
+        },
+        kind => bail!("daemon sent unknown field type {kind}"),
+      }
+    }
+
+    Ok(())
+  }
+
+  /// Reads the log messages the daemon sends before every response, until it
+  /// signals that the response follows. Fails if the daemon sent an error.
+  fn process_stderr(&mut self) -> Result<()> {
+    loop {
+      match self.read_u64()? {
+        STDERR_LAST => return Ok(()),
+
+        STDERR_NEXT => {
+          let message = self.read_string()?;
+          log::debug!("daemon: {message}");
+        },
+
+        STDERR_ERROR => {
+          let _kind = self.read_string()?;
+          let _level = self.read_u64()?;
+          let _name = self.read_string()?;
+          let message = self.read_string()?;
+          let _position = self.read_u64()?;
+
+          for _ in 0..self.read_u64()? {
+            let _position = self.read_u64()?;
+            let _trace = self.read_string()?;
+          }
+
+          bail!("daemon returned error: {message}");
+        },
+
+        STDERR_START_ACTIVITY => {
+          let _id = self.read_u64()?;
+          let _level = self.read_u64()?;
+          let _kind = self.read_u64()?;
+          let text = self.read_string()?;
+          self.skip_fields()?;
+          let _parent = self.read_u64()?;
+
+          log::trace!("daemon activity: {text}");
+        },
+
+        STDERR_STOP_ACTIVITY => {
+          self.read_u64()?;
+        },
+
+        STDERR_RESULT => {
+          let _id = self.read_u64()?;
+          let _kind = self.read_u64()?;
+          self.skip_fields()?;
+        },
+
+        message => bail!("daemon sent unknown message {message:#x}"),
+      }
+    }
+  }
+}
+
+/// A connection to the Nix daemon using the worker protocol.
+pub struct DaemonConnection {
+  stream:    RefCell<Stream>,
+  /// The minor protocol version both sides speak.
+  version:   u64,
+  store_dir: PathBuf,
+}
+
+/// Connects to the Nix daemon listening on `socket` and performs the
+/// handshake.
+///
+/// Paths sent by the daemon are validated against `store_dir`.
+pub fn connect_daemon(
+  socket: &Path,
+  store_dir: &Path,
+) -> Result<DaemonConnection> {
+  let socket_stream = UnixStream::connect(socket).with_context(|| {
+    format!(
+      "failed to connect to Nix daemon at {socket}",
+      socket = socket.display(),
+    )
+  })?;
+
+  let mut stream = Stream {
+    reader: BufReader::new(socket_stream.try_clone()?),
+    writer: BufWriter::new(socket_stream),
+  };
+
+  stream.write_u64(WORKER_MAGIC_1)?;
+  stream.writer.flush()?;
+
+  if stream.read_u64()? != WORKER_MAGIC_2 {
+    bail!("daemon sent an invalid handshake");
+  }
+
+  let daemon_version = stream.read_u64()?;
+  if daemon_version >> 8_u64 != PROTOCOL_VERSION >> 8_u64
+    || daemon_version < MINIMUM_PROTOCOL_VERSION
+  {
+    bail!(
+      "daemon speaks unsupported protocol version {major}.{minor}",
+      major = daemon_version >> 8,
+      minor = daemon_version & 0xFF,
+    );
+  }
+
+  let version = daemon_version.min(PROTOCOL_VERSION) & 0xFF;
+
+  stream.write_u64(PROTOCOL_VERSION)?;
+  // No CPU affinity.
+  stream.write_u64(0)?;
+  // Don't reserve space.
+  stream.write_u64(0)?;
+  stream.writer.flush()?;
+
+  if version >= 33 {
+    let daemon_nix_version = stream.read_string()?;
+    log::debug!("daemon Nix version: {daemon_nix_version}");
+  }
+
+  if version >= 35 {
+    // Whether we are trusted, which doesn't matter for queries.
+    stream.read_u64()?;
+  }
+
+  stream
+    .process_stderr()
+    .context("failed to complete handshake with daemon")?;
+
+  Ok(DaemonConnection {
+    stream: RefCell::new(stream),
+    version,
+    store_dir: store_dir.to_path_buf(),
+  })
+}
+
+impl DaemonConnection {
+  fn store_path(&self, path: String) -> Result<StorePath> {
+    StorePath::try_from_in(PathBuf::from(path), &self.store_dir)
+  }
+
+  fn path_to_str(path: &StorePath) -> Result<&str> {
+    path.to_str().with_context(|| {
+      format!(
+        "failed to convert path '{path}' to valid unicode",
+        path = path.display(),
+      )
+    })
+  }
+
+  /// Gets which of `paths` are valid, in a single round trip.
+  fn query_valid_paths<'a>(
+    &self,
+    paths: impl IntoIterator<Item = &'a StorePath>,
+  ) -> Result<HashSet<StorePath>> {
+    let paths = paths
+      .into_iter()
+      .map(Self::path_to_str)
+      .collect::<Result<Vec<_>>>()?;
+
+    let mut stream = self.stream.borrow_mut();
+
+    stream.write_u64(OP_QUERY_VALID_PATHS)?;
+    stream.write_strings(&paths)?;
+    if self.version >= 27 {
+      // Don't substitute paths that are not valid.
+      stream.write_u64(0)?;
+    }
+    stream.writer.flush()?;
+
+    stream.process_stderr()?;
+
+    stream
+      .read_strings()?
+      .into_iter()
+      .map(|path| self.store_path(path))
+      .collect()
+  }
+}
+
+impl ClosureSource for DaemonConnection {
+  fn query_path_info(&self, path: &StorePath) -> Result<PathInfo> {
+    let mut stream = self.stream.borrow_mut();
+
+    stream.write_u64(OP_QUERY_PATH_INFO)?;
+    stream.write_string(Self::path_to_str(path)?)?;
+    stream.writer.flush()?;
+
+    stream.process_stderr()?;
+
+    if stream.read_u64()? == 0 {
+      bail!(
+        "path '{path}' is not valid according to the daemon",
+        path = path.display(),
+      );
+    }
+
+    let deriver = stream.read_string()?;
+    let _nar_hash = stream.read_string()?;
+    let references = stream.read_strings()?;
+    let _registration_time = stream.read_u64()?;
+    let nar_size = stream.read_u64()?;
+    let _ultimate = stream.read_u64()?;
+    let _signatures = stream.read_strings()?;
+    let _content_address = stream.read_string()?;
+
+    drop(stream);
+
+    Ok(PathInfo {
+      path:       path.clone(),
+      references: references
+        .into_iter()
+        .map(|reference| self.store_path(reference))
+        .collect::<Result<_>>()?,
+      nar_size:   Size::from_bytes(nar_size),
+      deriver:    (!deriver.is_empty())
+        .then(|| self.store_path(deriver))
+        .transpose()?,
+    })
+  }
+
+  fn query_derivation_outputs(
+    &self,
+    path: &StorePath,
+  ) -> Result<BTreeMap<String, Option<StorePath>>> {
+    let mut stream = self.stream.borrow_mut();
+
+    stream.write_u64(OP_QUERY_DERIVATION_OUTPUT_MAP)?;
+    stream.write_string(Self::path_to_str(path)?)?;
+    stream.writer.flush()?;
+
+    stream.process_stderr()?;
+
+    let mut outputs = BTreeMap::new();
+
+    for _ in 0..stream.read_u64()? {
+      let name = stream.read_string()?;
+      let output = stream.read_string()?;
+
+      outputs.insert(
+        name,
+        (!output.is_empty())
+          .then(|| self.store_path(output))
+          .transpose()?,
+      );
+    }
+
+    drop(stream);
+
+    // The daemon knows the paths of input addressed outputs before they are
+    // built, so check which of them are.
+    let valid = self.query_valid_paths(outputs.values().flatten())?;
+
+    for output in outputs.values_mut() {
+      if output
+        .as_ref()
+        .is_some_and(|output| !valid.contains(output))
+      {
+        *output = None;
+      }
+    }
+
+    Ok(outputs)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{
+    collections::{
+      BTreeMap,
+      HashMap,
+    },
+    io::{
+      BufReader,
+      BufWriter,
+      Write as _,
+    },
+    os::unix::net::UnixListener,
+    path::{
+      Path,
+      PathBuf,
+    },
+    thread,
+  };
+
+  use size::Size;
+  use tempfile::TempDir;
+
+  use super::{
+    DaemonConnection,
+    OP_QUERY_DERIVATION_OUTPUT_MAP,
+    OP_QUERY_PATH_INFO,
+    OP_QUERY_VALID_PATHS,
+    PROTOCOL_VERSION,
+    STDERR_ERROR,
+    STDERR_LAST,
+    STDERR_NEXT,
+    Stream,
+    WORKER_MAGIC_1,
+    WORKER_MAGIC_2,
+    connect_daemon,
+  };
+  use crate::{
+    ClosureSource as _,
+    StorePath,
+  };
+
+  const HELLO: &str =
+    "/nix/store/0004yybkm5hnwjyxv129js3mjp7kbrax-hello-2.12.1";
+  const GLIBC: &str =
+    "/nix/store/1114yybkm5hnwjyxv129js3mjp7kbrax-glibc-2.40-66";
+  const HELLO_DRV: &str =
+    "/nix/store/3334yybkm5hnwjyxv129js3mjp7kbrax-hello-2.12.1.drv";
+  const HELLO_DOC: &str =
+    "/nix/store/4444yybkm5hnwjyxv129js3mjp7kbrax-hello-2.12.1-doc";
+  /// A derivation whose outputs the daemon fails to check.
+  const BROKEN_DRV: &str =
+    "/nix/store/5554yybkm5hnwjyxv129js3mjp7kbrax-broken.drv";
+  const BROKEN: &str = "/nix/store/6664yybkm5hnwjyxv129js3mjp7kbrax-broken";
+
+  fn write_error(stream: &mut Stream, message: &str) {
+    stream.write_u64(STDERR_ERROR).unwrap();
+    stream.write_string("Error").unwrap();
+    stream.write_u64(0).unwrap();
+    stream.write_string("Error").unwrap();
+    stream.write_string(message).unwrap();
+    stream.write_u64(0).unwrap();
+    stream.write_u64(0).unwrap();
+  }
+
+  /// Serves a single client like a daemon would, knowing about `paths`.
+  fn serve(stream: &mut Stream, paths: &HashMap<&str, (&[&str], u64)>) {
+    assert_eq!(stream.read_u64().unwrap(), WORKER_MAGIC_1);
+    stream.write_u64(WORKER_MAGIC_2).unwrap();
+    stream.write_u64(PROTOCOL_VERSION).unwrap();
+    stream.writer.flush().unwrap();
+
+    assert_eq!(stream.read_u64().unwrap(), PROTOCOL_VERSION);
+    assert_eq!(stream.read_u64().unwrap(), 0);
+    assert_eq!(stream.read_u64().unwrap(), 0);
+
+    stream.write_string("2.24.0").unwrap();
+    stream.write_u64(1).unwrap();
+    stream.write_u64(STDERR_LAST).unwrap();
+    stream.writer.flush().unwrap();
+
+    while let Ok(op) = stream.read_u64() {
+      stream.write_u64(STDERR_NEXT).unwrap();
+      stream.write_string("querying paths").unwrap();
+
+      match op {
+        OP_QUERY_PATH_INFO => {
+          let path = stream.read_string().unwrap();
+          stream.write_u64(STDERR_LAST).unwrap();
+
+          let Some(&(references, nar_size)) = paths.get(&*path) else {
+            stream.write_u64(0).unwrap();
+            stream.writer.flush().unwrap();
+            continue;
+          };
+
+          stream.write_u64(1).unwrap();
+          stream.write_string("").unwrap();
+          stream.write_string("sha256-AAAA").unwrap();
+          stream.write_u64(references.len() as u64).unwrap();
+          for reference in references {
+            stream.write_string(reference).unwrap();
+          }
+          stream.write_u64(0).unwrap();
+          stream.write_u64(nar_size).unwrap();
+          stream.write_u64(0).unwrap();
+          stream.write_u64(0).unwrap();
+          stream.write_string("").unwrap();
+        },
+
+        OP_QUERY_VALID_PATHS => {
+          let requested = stream.read_strings().unwrap();
+          assert_eq!(stream.read_u64().unwrap(), 0, "should not substitute");
+
+          if requested.iter().any(|path| path == BROKEN) {
+            write_error(stream, "database is locked");
+          } else {
+            let valid = requested
+              .iter()
+              .filter(|path| paths.contains_key(path.as_str()))
+              .map(String::as_str)
+              .collect::<Vec<_>>();
+
+            stream.write_u64(STDERR_LAST).unwrap();
+            stream.write_strings(&valid).unwrap();
+          }
+        },
+
+        OP_QUERY_DERIVATION_OUTPUT_MAP => {
+          let path = stream.read_string().unwrap();
+          let outputs = match &*path {
+            HELLO_DRV => &[("doc", HELLO_DOC), ("out", HELLO)][..],
+            BROKEN_DRV => &[("out", BROKEN)][..],
+            _ => &[],
+          };
+
+          stream.write_u64(STDERR_LAST).unwrap();
+          stream.write_u64(outputs.len() as u64).unwrap();
+          for &(name, output) in outputs {
+            stream.write_string(name).unwrap();
+            stream.write_string(output).unwrap();
+          }
+        },
+
+        _ => write_error(stream, "unsupported operation"),
+      }
+
+      stream.writer.flush().unwrap();
+    }
+  }
+
+  /// Connects to a mock daemon knowing about `hello` and its dependency
+  /// `glibc`, listening on a socket in the returned directory.
+  fn connect_mock_daemon() -> (TempDir, DaemonConnection) {
+    let dir = tempfile::tempdir().unwrap();
+    let socket = dir.path().join("socket");
+
+    let listener = UnixListener::bind(&socket).unwrap();
+
+    thread::spawn(move || {
+      let paths = HashMap::from([
+        (HELLO, (&[GLIBC, HELLO][..], 226_560)),
+        (GLIBC, (&[][..], 30_000_000)),
+      ]);
+
+      let (socket_stream, _) = listener.accept().unwrap();
+      let mut stream = Stream {
+        reader: BufReader::new(socket_stream.try_clone().unwrap()),
+        writer: BufWriter::new(socket_stream),
+      };
+
+      serve(&mut stream, &paths);
+    });
+
+    let daemon = connect_daemon(&socket, Path::new("/nix/store")).unwrap();
+
+    (dir, daemon)
+  }
+
+  fn store_path(path: &str) -> StorePath {
+    StorePath::try_from(PathBuf::from(path)).unwrap()
+  }
+
+  #[test]
+  fn daemon_closure_size() {
+    let (_dir, daemon) = connect_mock_daemon();
+
+    assert_eq!(
+      daemon.query_closure_size(&store_path(HELLO)).unwrap(),
+      Size::from_bytes(30_226_560_u64),
+    );
+  }
+
+  #[test]
+  fn daemon_invalid_path() {
+    let (_dir, daemon) = connect_mock_daemon();
+
+    daemon
+      .query_path_info(&store_path(
+        "/nix/store/2224yybkm5hnwjyxv129js3mjp7kbrax-invalid",
+      ))
+      .unwrap_err();
+
+    // The connection is still usable afterwards.
+    daemon.query_path_info(&store_path(GLIBC)).unwrap();
+  }
+
+  #[test]
+  fn daemon_derivation_outputs() {
+    let (_dir, daemon) = connect_mock_daemon();
+
+    assert_eq!(
+      daemon
+        .query_derivation_outputs(&store_path(HELLO_DRV))
+        .unwrap(),
+      BTreeMap::from([
+        ("doc".to_owned(), None),
+        ("out".to_owned(), Some(store_path(HELLO))),
+      ]),
+    );
+  }
+
+  #[test]
+  fn daemon_derivation_outputs_error() {
+    let (_dir, daemon) = connect_mock_daemon();
+
+    // Failing to check an output is not the same as it not being built.
+    let error = daemon
+      .query_derivation_outputs(&store_path(BROKEN_DRV))
+      .unwrap_err();
+    assert!(format!("{error:#}").contains("database is locked"));
+  }
+}
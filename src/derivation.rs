@@ -0,0 +1,1072 @@
+use std::{
+  collections::{
+    BTreeMap,
+    BTreeSet,
+    HashMap,
+    HashSet,
+    VecDeque,
+  },
+  fmt::Write as _,
+  fs,
+  path::PathBuf,
+  str,
+};
+
+use anyhow::{
+  Context as _,
+  Error,
+  Result,
+  bail,
+};
+use itertools::{
+  EitherOrBoth,
+  Itertools as _,
+};
+use sha2::{
+  Digest as _,
+  Sha256,
+};
+use size::Size;
+
+use crate::{
+  ClosureSource,
+  Diff,
+  Dump,
+  PathInfo,
+  StoreDirs,
+  StorePath,
+  diff::pair_paths,
+};
+
+/// An output of a [`Derivation`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DerivationOutput {
+  /// The path of the output, which is empty for content addressed
+  /// derivations that aren't fixed output.
+  pub path:      String,
+  /// How the output is hashed for content addressed derivations, like
+  /// `r:sha256`, and empty otherwise.
+  pub hash_algo: String,
+  /// The expected hash of fixed output derivations, encoded in hexadecimal.
+  pub hash:      String,
+}
+
+/// A derivation as serialised to a `.drv` file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Derivation {
+  /// The outputs by name.
+  pub outputs:           BTreeMap<String, DerivationOutput>,
+  /// The paths of the input derivations and the outputs used of each.
+  pub input_derivations: BTreeMap<String, BTreeSet<String>>,
+  /// The store paths used as inputs that are not built, like sources.
+  pub input_sources:     BTreeSet<String>,
+  /// The system the derivation is built on, like `x86_64-linux`.
+  pub system:            String,
+  /// The program that builds the derivation.
+  pub builder:           String,
+  /// The arguments passed to the builder.
+  pub args:              Vec<String>,
+  /// The environment variables passed to the builder.
+  pub env:               BTreeMap<String, String>,
+}
+
+/// Parses the serialisation of a derivation found in `.drv` files.
+struct Parser<'a> {
+  rest: &'a str,
+}
+
+impl Parser<'_> {
+  fn expect(&mut self, token: &str) -> Result<()> {
+    let Some(rest) = self.rest.strip_prefix(token) else {
+      bail!(
+        "expected '{token}', found '{found}'",
+        found = self.rest.chars().take(16).collect::<String>(),
+      );
+    };
+
+    self.rest = rest;
+    Ok(())
+  }
+
+  fn string(&mut self) -> Result<String> {
+    self.expect("\"")?;
+
+    let mut string = String::new();
+    let mut chars = self.rest.char_indices();
+
+    while let Some((index, char)) = chars.next() {
+      match char {
+        '"' => {
+          self.rest = &self.rest[index + 1..];
+          return Ok(string);
+        },
+        '\\' => {
+          let Some((_, escaped)) = chars.next() else {
+            break;
+          };
+
+          string.push(match escaped {
+            'n' => '\n',
+            'r' => '\r',
+            't' => '\t',
+            other => other,
+          });
+        },
+        other => string.push(other),
+      }
+    }
+
+    bail!("unterminated string")
+  }
+
+  fn list<T>(
+    &mut self,
+    mut item: impl FnMut(&mut Self) -> Result<T>,
+  ) -> Result<Vec<T>> {
+    self.expect("[")?;
+
+    let mut items = Vec::new();
+
+    if self.expect("]").is_ok() {
+      return Ok(items);
+    }
+
+    loop {
+      items.push(item(self)?);
+
+      if self.expect(",").is_err() {
+        break;
+      }
+    }
+
+    self.expect("]")?;
+    Ok(items)
+  }
+}
+
+impl str::FromStr for Derivation {
+  type Err = Error;
+
+  fn from_str(aterm: &str) -> Result<Self> {
+    let mut parser = Parser {
+      rest: aterm.trim_end(),
+    };
+
+    parser.expect("Derive(")?;
+
+    let outputs = parser.list(|parser| {
+      parser.expect("(")?;
+      let name = parser.string()?;
+      parser.expect(",")?;
+      let path = parser.string()?;
+      parser.expect(",")?;
+      let hash_algo = parser.string()?;
+      parser.expect(",")?;
+      let hash = parser.string()?;
+      parser.expect(")")?;
+
+      Ok((name, DerivationOutput {
+        path,
+        hash_algo,
+        hash,
+      }))
+    })?;
+    parser.expect(",")?;
+
+    let input_derivations = parser.list(|parser| {
+      parser.expect("(")?;
+      let path = parser.string()?;
+      parser.expect(",")?;
+      let outputs = parser.list(Parser::string)?;
+      parser.expect(")")?;
+
+      Ok((path, outputs.into_iter().collect()))
+    })?;
+    parser.expect(",")?;
+
+    let input_sources = parser.list(Parser::string)?;
+    parser.expect(",")?;
+    let system = parser.string()?;
+    parser.expect(",")?;
+    let builder = parser.string()?;
+    parser.expect(",")?;
+    let args = parser.list(Parser::string)?;
+    parser.expect(",")?;
+
+    let env = parser.list(|parser| {
+      parser.expect("(")?;
+      let name = parser.string()?;
+      parser.expect(",")?;
+      let value = parser.string()?;
+      parser.expect(")")?;
+
+      Ok((name, value))
+    })?;
+    parser.expect(")")?;
+
+    if !parser.rest.is_empty() {
+      bail!("unexpected trailing data after derivation");
+    }
+
+    Ok(Self {
+      outputs: outputs.into_iter().collect(),
+      input_derivations: input_derivations.into_iter().collect(),
+      input_sources: input_sources.into_iter().collect(),
+      system,
+      builder,
+      args,
+      env: env.into_iter().collect(),
+    })
+  }
+}
+
+/// Appends `string` to `aterm` as a quoted and escaped string.
+fn write_string(aterm: &mut String, string: &str) {
+  aterm.push('"');
+
+  for char in string.chars() {
+    match char {
+      '"' => aterm.push_str("\\\""),
+      '\\' => aterm.push_str("\\\\"),
+      '\n' => aterm.push_str("\\n"),
+      '\r' => aterm.push_str("\\r"),
+      '\t' => aterm.push_str("\\t"),
+      other => aterm.push(other),
+    }
+  }
+
+  aterm.push('"');
+}
+
+/// Appends the items of `items` to `aterm` as a list, writing each with
+/// `write`.
+fn write_list<T>(
+  aterm: &mut String,
+  items: impl IntoIterator<Item = T>,
+  mut write: impl FnMut(&mut String, T),
+) {
+  aterm.push('[');
+
+  for (index, item) in items.into_iter().enumerate() {
+    if index > 0 {
+      aterm.push(',');
+    }
+
+    write(aterm, item);
+  }
+
+  aterm.push(']');
+}
+
+/// Encodes `bytes` in hexadecimal.
+pub(crate) fn hex(bytes: &[u8]) -> String {
+  bytes.iter().fold(String::new(), |mut hex, byte| {
+    let _ = write!(hex, "{byte:02x}");
+    hex
+  })
+}
+
+/// Returns the SHA-256 hash of `data`, encoded in hexadecimal.
+fn sha256_hex(data: &[u8]) -> String {
+  hex(&Sha256::digest(data))
+}
+
+impl Derivation {
+  /// Serialises the derivation like it is stored in `.drv` files, with
+  /// `input_derivations` in place of its own.
+  ///
+  /// If `mask_outputs` is set, the output paths and the environment variables
+  /// named after outputs are left empty.
+  fn unparse(
+    &self,
+    input_derivations: &BTreeMap<String, BTreeSet<String>>,
+    mask_outputs: bool,
+  ) -> String {
+    let mut aterm = String::from("Derive(");
+
+    write_list(&mut aterm, &self.outputs, |aterm, (name, output)| {
+      aterm.push('(');
+      write_string(aterm, name);
+      aterm.push(',');
+      write_string(aterm, if mask_outputs { "" } else { &output.path });
+      aterm.push(',');
+      write_string(aterm, &output.hash_algo);
+      aterm.push(',');
+      write_string(aterm, &output.hash);
+      aterm.push(')');
+    });
+    aterm.push(',');
+
+    write_list(&mut aterm, input_derivations, |aterm, (path, outputs)| {
+      aterm.push('(');
+      write_string(aterm, path);
+      aterm.push(',');
+      write_list(aterm, outputs, |aterm, output| write_string(aterm, output));
+      aterm.push(')');
+    });
+    aterm.push(',');
+
+    write_list(&mut aterm, &self.input_sources, |aterm, path| {
+      write_string(aterm, path);
+    });
+    aterm.push(',');
+    write_string(&mut aterm, &self.system);
+    aterm.push(',');
+    write_string(&mut aterm, &self.builder);
+    aterm.push(',');
+    write_list(&mut aterm, &self.args, |aterm, arg| {
+      write_string(aterm, arg);
+    });
+    aterm.push(',');
+
+    write_list(&mut aterm, &self.env, |aterm, (name, value)| {
+      aterm.push('(');
+      write_string(aterm, name);
+      aterm.push(',');
+      write_string(
+        aterm,
+        if mask_outputs && self.outputs.contains_key(name) {
+          ""
+        } else {
+          value
+        },
+      );
+      aterm.push(')');
+    });
+    aterm.push(')');
+
+    aterm
+  }
+
+  /// Computes the hash of every output of the derivation modulo fixed output
+  /// derivations, like Nix does to identify the outputs of content addressed
+  /// derivations before they are built.
+  ///
+  /// Input derivations are replaced by their own hashes, which
+  /// `input_hashes` returns for the path of an input derivation, so changing
+  /// how a fixed output is fetched doesn't change the hashes of its
+  /// dependents.
+  fn hash_modulo(
+    &self,
+    mask_outputs: bool,
+    input_hashes: &mut dyn FnMut(&str) -> Result<BTreeMap<String, String>>,
+  ) -> Result<BTreeMap<String, String>> {
+    if !self.outputs.is_empty()
+      && self.outputs.values().all(|output| !output.hash.is_empty())
+    {
+      return Ok(
+        self
+          .outputs
+          .iter()
+          .map(|(name, output)| {
+            let fixed = format!(
+              "fixed:out:{hash_algo}:{hash}:{path}",
+              hash_algo = output.hash_algo,
+              hash = output.hash,
+              path = output.path,
+            );
+
+            (name.clone(), sha256_hex(fixed.as_bytes()))
+          })
+          .collect(),
+      );
+    }
+
+    let mut input_derivations = BTreeMap::<String, BTreeSet<String>>::new();
+
+    for (input, outputs) in &self.input_derivations {
+      let hashes = input_hashes(input)?;
+
+      for output in outputs {
+        let hash = hashes.get(output).with_context(|| {
+          format!("derivation '{input}' has no output '{output}'")
+        })?;
+
+        input_derivations
+          .entry(hash.clone())
+          .or_default()
+          .insert(output.clone());
+      }
+    }
+
+    let hash =
+      sha256_hex(self.unparse(&input_derivations, mask_outputs).as_bytes());
+
+    Ok(
+      self
+        .outputs
+        .keys()
+        .map(|name| (name.clone(), hash.clone()))
+        .collect(),
+    )
+  }
+
+  /// Computes the hash of every output of the derivation at `path` modulo
+  /// fixed output derivations, reading the input derivations from the store
+  /// of `dirs`. The hashes of input derivations are remembered in `hashes`.
+  fn hash_modulo_at(
+    path: &StorePath,
+    dirs: &StoreDirs,
+    mask_outputs: bool,
+    hashes: &mut HashMap<StorePath, BTreeMap<String, String>>,
+  ) -> Result<BTreeMap<String, String>> {
+    Self::read(path, dirs)?.hash_modulo(mask_outputs, &mut |input| {
+      let input = StorePath::try_from_in(PathBuf::from(input), &dirs.store)?;
+
+      if let Some(input_hashes) = hashes.get(&input) {
+        return Ok(input_hashes.clone());
+      }
+
+      let input_hashes = Self::hash_modulo_at(&input, dirs, false, hashes)?;
+      hashes.insert(input, input_hashes.clone());
+
+      Ok(input_hashes)
+    })
+  }
+
+  /// Computes the hashes the outputs of the derivation at `path` are
+  /// registered under in the `Realisations` table of the Nix database once
+  /// built, like `sha256:1f5b...`.
+  ///
+  /// Every input derivation has to be in the store of `dirs`.
+  #[expect(clippy::missing_errors_doc)]
+  pub fn output_hashes(
+    path: &StorePath,
+    dirs: &StoreDirs,
+  ) -> Result<BTreeMap<String, String>> {
+    Ok(
+      Self::hash_modulo_at(path, dirs, true, &mut HashMap::new())?
+        .into_iter()
+        .map(|(name, hash)| (name, format!("sha256:{hash}")))
+        .collect(),
+    )
+  }
+
+  /// Reads and parses the `.drv` file at `path` from the store of `dirs`.
+  #[expect(clippy::missing_errors_doc)]
+  pub fn read(path: &StorePath, dirs: &StoreDirs) -> Result<Self> {
+    let aterm = fs::read_to_string(dirs.physical(path)).with_context(|| {
+      format!("failed to read derivation '{path}'", path = path.display())
+    })?;
+
+    aterm.parse().with_context(|| {
+      format!("failed to parse derivation '{path}'", path = path.display())
+    })
+  }
+}
+
+/// The closure of the outputs of a derivation, read from the `.drv` files of
+/// it and its input derivations, so closures can be diffed before they are
+/// built.
+///
+/// Every output references the outputs of the input derivations it uses and
+/// its input sources, like patches and builder scripts, so this is the
+/// closure of everything needed to build it rather than the closure it
+/// depends on at runtime. Outputs of content addressed
+/// derivations are left out, since their paths are only known once built.
+#[derive(Debug, Clone)]
+pub struct DerivationClosure {
+  paths:   Dump,
+  root:    StorePath,
+  unbuilt: Vec<StorePath>,
+}
+
+impl DerivationClosure {
+  /// Reads the closure of the derivation at `path` from the store of `dirs`.
+  ///
+  /// The NAR sizes of outputs that are already built are queried from
+  /// `store`, the others are counted as unbuilt with a size of zero.
+  #[expect(clippy::missing_errors_doc)]
+  pub fn read(
+    path: &StorePath,
+    dirs: &StoreDirs,
+    store: &dyn ClosureSource,
+  ) -> Result<Self> {
+    let store_path =
+      |path: &str| StorePath::try_from_in(PathBuf::from(path), &dirs.store);
+
+    let mut derivations = BTreeMap::new();
+    let mut queue = VecDeque::from([path.clone()]);
+
+    while let Some(path) = queue.pop_front() {
+      if derivations.contains_key(&path) {
+        continue;
+      }
+
+      let derivation = Derivation::read(&path, dirs)?;
+
+      for input in derivation.input_derivations.keys() {
+        queue.push_back(store_path(input)?);
+      }
+
+      derivations.insert(path, derivation);
+    }
+
+    log::info!(
+      "found {count} derivations in closure of '{path}'",
+      count = derivations.len(),
+      path = path.display(),
+    );
+
+    let mut infos = Vec::new();
+    let mut unbuilt = Vec::new();
+    let mut sources = BTreeSet::new();
+
+    for (deriver, derivation) in &derivations {
+      let mut references = Vec::new();
+
+      for source in &derivation.input_sources {
+        let source = store_path(source)?;
+
+        references.push(source.clone());
+        sources.insert(source);
+      }
+
+      for (input, outputs) in &derivation.input_derivations {
+        let input = &derivations[&store_path(input)?];
+
+        for output in outputs {
+          match input.outputs.get(output) {
+            Some(output) if !output.path.is_empty() => {
+              references.push(store_path(&output.path)?);
+            },
+            _ => {},
+          }
+        }
+      }
+
+      for output in derivation.outputs.values() {
+        if output.path.is_empty() {
+          continue;
+        }
+
+        let output = store_path(&output.path)?;
+        let nar_size = match store.query_path_info(&output) {
+          Ok(info) => info.nar_size,
+          Err(_) => {
+            unbuilt.push(output.clone());
+            Size::from_bytes(0_u64)
+          },
+        };
+
+        infos.push(PathInfo {
+          path: output,
+          references: references.clone(),
+          nar_size,
+          deriver: Some(deriver.clone()),
+        });
+      }
+    }
+
+    // Sources are added to the store before the derivations using them, so
+    // they are always there unless the closure was garbage collected.
+    for source in sources {
+      let nar_size = match store.query_path_info(&source) {
+        Ok(info) => info.nar_size,
+        Err(error) => {
+          log::warn!(
+            "failed to query size of source '{path}': {error}",
+            path = source.display(),
+          );
+          Size::from_bytes(0_u64)
+        },
+      };
+
+      infos.push(PathInfo {
+        path: source,
+        references: Vec::new(),
+        nar_size,
+        deriver: None,
+      });
+    }
+
+    let outputs = &derivations[path].outputs;
+    let name = main_output(outputs.keys(), path)?;
+    let root = &outputs[name].path;
+
+    if root.is_empty() {
+      bail!(
+        "output '{name}' of derivation '{path}' has no known path",
+        path = path.display(),
+      );
+    }
+
+    unbuilt.sort();
+
+    Ok(Self {
+      paths: infos.into_iter().collect(),
+      root: store_path(root)?,
+      unbuilt,
+    })
+  }
+
+  /// Returns the output the closure is rooted at, which is `out` if the
+  /// derivation has one and its only output otherwise.
+  #[must_use]
+  pub fn root(&self) -> &StorePath {
+    &self.root
+  }
+
+  /// Returns the outputs in the closure that are not built yet, sorted.
+  #[must_use]
+  pub fn unbuilt(&self) -> &[StorePath] {
+    &self.unbuilt
+  }
+}
+
+impl ClosureSource for DerivationClosure {
+  fn query_path_info(&self, path: &StorePath) -> Result<PathInfo> {
+    self.paths.query_path_info(path)
+  }
+}
+
+/// What differs between two derivations of a package, explaining why its
+/// outputs changed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DerivationDiff {
+  /// The paths of the derivations.
+  pub paths:             Diff<StorePath>,
+  /// The input derivations only used by the old or new derivation.
+  pub input_derivations: Diff<Vec<String>>,
+  /// The input sources only used by the old or new derivation.
+  pub input_sources:     Diff<Vec<String>>,
+  /// The systems, if they differ.
+  pub system:            Option<Diff<String>>,
+  /// The builders, if they differ.
+  pub builder:           Option<Diff<String>>,
+  /// The builder arguments, if they differ.
+  pub args:              Option<Diff<Vec<String>>>,
+  /// The environment variables that differ, with `None` for variables only
+  /// set by the other derivation.
+  ///
+  /// Variables named after an output are left out, since they hold the output
+  /// paths that change whenever anything else does.
+  pub env:               Vec<(String, Diff<Option<String>>)>,
+}
+
+/// Returns the items of `old` and `new` only found in one of them.
+fn difference<'a>(
+  old: impl IntoIterator<Item = &'a String>,
+  new: impl IntoIterator<Item = &'a String>,
+) -> Diff<Vec<String>> {
+  let old = old.into_iter().collect::<BTreeSet<_>>();
+  let new = new.into_iter().collect::<BTreeSet<_>>();
+
+  Diff {
+    old: old.difference(&new).map(|&item| item.clone()).collect(),
+    new: new.difference(&old).map(|&item| item.clone()).collect(),
+  }
+}
+
+/// Returns `Some` if `old` and `new` differ.
+fn changed<T: PartialEq + Clone>(old: &T, new: &T) -> Option<Diff<T>> {
+  (old != new).then(|| {
+    Diff {
+      old: old.clone(),
+      new: new.clone(),
+    }
+  })
+}
+
+/// Returns which of the outputs named `names` of the derivation at `path`
+/// stands for the derivation, which is `out`, or the only output if there is
+/// no `out`.
+///
+/// Fails if there are several outputs and none of them is `out`, instead of
+/// guessing which one is meant.
+#[expect(clippy::missing_errors_doc)]
+pub fn main_output<'a>(
+  names: impl IntoIterator<Item = &'a String>,
+  path: &StorePath,
+) -> Result<&'a str> {
+  let names = names.into_iter().map(String::as_str).collect::<Vec<_>>();
+
+  match names[..] {
+    _ if names.contains(&"out") => Ok("out"),
+    [name] => Ok(name),
+    [] => bail!("derivation '{path}' has no outputs", path = path.display()),
+    ref names => {
+      bail!(
+        "derivation '{path}' has no 'out' output, pass the path of one of its \
+         outputs {names} instead",
+        path = path.display(),
+        names = names.iter().map(|name| format!("'{name}'")).join(", "),
+      )
+    },
+  }
+}
+
+/// Returns the name of the derivation at `path`, without the hash.
+fn derivation_name(path: &StorePath) -> String {
+  let name = path
+    .file_name()
+    .map_or_else(String::new, |name| name.to_string_lossy().into_owned());
+
+  match name.split_once('-') {
+    Some((_, name)) => name.to_owned(),
+    None => name,
+  }
+}
+
+/// Returns the derivers of the paths of `package` in `infos` that are not in
+/// `other`.
+fn derivers(
+  infos: &[PathInfo],
+  other: &HashSet<&StorePath>,
+  package: &str,
+) -> BTreeSet<StorePath> {
+  let mut derivers = BTreeSet::new();
+
+  for info in infos {
+    if other.contains(&info.path)
+      || !info
+        .path
+        .parse_name_and_version()
+        .is_ok_and(|(name, _)| name == package)
+    {
+      continue;
+    }
+
+    match info.deriver {
+      Some(ref deriver) => {
+        derivers.insert(deriver.clone());
+      },
+      None => {
+        log::warn!(
+          "path '{path}' has no known deriver",
+          path = info.path.display()
+        );
+      },
+    }
+  }
+
+  derivers
+}
+
+impl DerivationDiff {
+  /// Queries the closures of `path_old` and `path_new` from `source_old` and
+  /// `source_new` respectively, and diffs the derivations of the paths of
+  /// `package` that are only in one of them.
+  ///
+  /// Derivations are paired by name. If there is only a single unpaired
+  /// derivation on both sides, those are paired as well, so version changes
+  /// are covered. The derivations are read from the store of `dirs`.
+  #[expect(clippy::missing_errors_doc)]
+  pub fn query(
+    source_old: &dyn ClosureSource,
+    path_old: &StorePath,
+    source_new: &dyn ClosureSource,
+    path_new: &StorePath,
+    package: &str,
+    dirs: &StoreDirs,
+  ) -> Result<Vec<Self>> {
+    let query = |source: &dyn ClosureSource, path: &StorePath| {
+      source.query_closure_infos(path).with_context(|| {
+        format!(
+          "failed to query dependencies of path '{path}'",
+          path = path.display()
+        )
+      })
+    };
+
+    let infos_old = query(source_old, path_old)?;
+    let infos_new = query(source_new, path_new)?;
+
+    let paths_old = infos_old
+      .iter()
+      .map(|info| &info.path)
+      .collect::<HashSet<_>>();
+    let paths_new = infos_new
+      .iter()
+      .map(|info| &info.path)
+      .collect::<HashSet<_>>();
+
+    let by_name = |derivers: BTreeSet<StorePath>| {
+      let mut by_name = BTreeMap::<_, Vec<_>>::new();
+
+      for deriver in derivers {
+        by_name
+          .entry(derivation_name(&deriver))
+          .or_default()
+          .push(deriver);
+      }
+
+      by_name
+    };
+
+    let derivers_old = by_name(derivers(&infos_old, &paths_new, package));
+    let derivers_new = by_name(derivers(&infos_new, &paths_old, package));
+
+    let pairs = pair_paths(derivers_old, derivers_new, "derivation");
+
+    if pairs.is_empty() {
+      bail!("package '{package}' has no changed derivations to compare");
+    }
+
+    pairs
+      .into_iter()
+      .map(|paths| {
+        let derivations = Diff {
+          old: Derivation::read(&paths.old, dirs)?,
+          new: Derivation::read(&paths.new, dirs)?,
+        };
+
+        Ok(Self::from_derivations(paths, &derivations))
+      })
+      .collect()
+  }
+
+  /// Compares the derivations at `paths`.
+  #[must_use]
+  pub fn from_derivations(
+    paths: Diff<StorePath>,
+    derivations: &Diff<Derivation>,
+  ) -> Self {
+    let Diff { ref old, ref new } = *derivations;
+
+    let env = old
+      .env
+      .iter()
+      .merge_join_by(&new.env, |this, that| this.0.cmp(that.0))
+      .filter_map(|entry| {
+        let (name, value_old, value_new) = match entry {
+          EitherOrBoth::Both((name, old), (_, new)) => {
+            (name, Some(old), Some(new))
+          },
+          EitherOrBoth::Left((name, old)) => (name, Some(old), None),
+          EitherOrBoth::Right((name, new)) => (name, None, Some(new)),
+        };
+
+        (value_old != value_new
+          && !old.outputs.contains_key(name)
+          && !new.outputs.contains_key(name))
+        .then(|| {
+          (name.clone(), Diff {
+            old: value_old.cloned(),
+            new: value_new.cloned(),
+          })
+        })
+      })
+      .collect();
+
+    Self {
+      paths,
+      input_derivations: difference(
+        old.input_derivations.keys(),
+        new.input_derivations.keys(),
+      ),
+      input_sources: difference(&old.input_sources, &new.input_sources),
+      system: changed(&old.system, &new.system),
+      builder: changed(&old.builder, &new.builder),
+      args: changed(&old.args, &new.args),
+      env,
+    }
+  }
+
+  /// Returns whether the derivations only differ in their output paths.
+  #[must_use]
+  pub fn is_empty(&self) -> bool {
+    self.input_derivations.old.is_empty()
+      && self.input_derivations.new.is_empty()
+      && self.input_sources.old.is_empty()
+      && self.input_sources.new.is_empty()
+      && self.system.is_none()
+      && self.builder.is_none()
+      && self.args.is_none()
+      && self.env.is_empty()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::{
+    BTreeMap,
+    HashMap,
+  };
+
+  use super::{
+    Derivation,
+    DerivationDiff,
+    main_output,
+  };
+  use crate::{
+    Diff,
+    test_utils::store_path_with_hash,
+  };
+
+  const OLD: &str = r#"Derive([("out","/nix/store/0004yybkm5hnwjyxv129js3mjp7kbrax-curl-8.5.0","","")],[("/nix/store/1114yybkm5hnwjyxv129js3mjp7kbrax-openssl-3.0.13.drv",["dev","out"]),("/nix/store/2224yybkm5hnwjyxv129js3mjp7kbrax-bash-5.2.drv",["out"])],["/nix/store/3334yybkm5hnwjyxv129js3mjp7kbrax-default-builder.sh"],"x86_64-linux","/nix/store/4444yybkm5hnwjyxv129js3mjp7kbrax-bash-5.2/bin/bash",["-e","/nix/store/3334yybkm5hnwjyxv129js3mjp7kbrax-default-builder.sh"],[("configureFlags","--with-openssl"),("name","curl-8.5.0"),("out","/nix/store/0004yybkm5hnwjyxv129js3mjp7kbrax-curl-8.5.0"),("postInstall","echo \"done\"\nexit 0")])"#;
+
+  const NEW: &str = r#"Derive([("out","/nix/store/5554yybkm5hnwjyxv129js3mjp7kbrax-curl-8.5.0","","")],[("/nix/store/6664yybkm5hnwjyxv129js3mjp7kbrax-openssl-3.0.14.drv",["dev","out"]),("/nix/store/2224yybkm5hnwjyxv129js3mjp7kbrax-bash-5.2.drv",["out"])],["/nix/store/3334yybkm5hnwjyxv129js3mjp7kbrax-default-builder.sh"],"x86_64-linux","/nix/store/4444yybkm5hnwjyxv129js3mjp7kbrax-bash-5.2/bin/bash",["-e","/nix/store/3334yybkm5hnwjyxv129js3mjp7kbrax-default-builder.sh"],[("configureFlags","--with-openssl --with-zstd"),("name","curl-8.5.0"),("out","/nix/store/5554yybkm5hnwjyxv129js3mjp7kbrax-curl-8.5.0"),("separateDebugInfo","1")])"#;
+
+  /// A fixed output derivation fetching the sources of `HELLO`.
+  const FETCH: &str = r#"Derive([("out","/nix/store/pa10z4ngm0g83kx9mssrqzz30s84vq7k-hello-2.12.1.tar.gz","sha256","8d99142afd92576f30b0cd7cb42a8dc6809998bc5d607d88761f512e26c7db20")],[],[],"builtin","builtin:fetchurl",[],[("builder","builtin:fetchurl"),("name","hello-2.12.1.tar.gz"),("out","/nix/store/pa10z4ngm0g83kx9mssrqzz30s84vq7k-hello-2.12.1.tar.gz"),("outputHash","8d99142afd92576f30b0cd7cb42a8dc6809998bc5d607d88761f512e26c7db20"),("outputHashAlgo","sha256"),("system","builtin"),("url","https://ftp.gnu.org/gnu/hello/hello-2.12.1.tar.gz")])"#;
+  const FETCH_DRV: &str =
+    "/nix/store/x62vr2hgqd91mvvfnnq7m3vqd5039csg-hello-2.12.1.tar.gz.drv";
+
+  /// An input addressed derivation whose output paths are derived from its
+  /// hash modulo fixed output derivations, like Nix does.
+  const HELLO: &str = r#"Derive([("man","/nix/store/mnhlpg1k3v74ci2c0x1x5sjhfywcfgx4-hello-2.12.1-man","",""),("out","/nix/store/la5dknhpig80236c7yp07hjjkkvc8q44-hello-2.12.1","","")],[("/nix/store/x62vr2hgqd91mvvfnnq7m3vqd5039csg-hello-2.12.1.tar.gz.drv",["out"])],["/nix/store/9krlzvny65gdc8s7kpb6lkx8cd02c25b-default-builder.sh"],"x86_64-linux","/bin/sh",["-e","/nix/store/9krlzvny65gdc8s7kpb6lkx8cd02c25b-default-builder.sh"],[("builder","/bin/sh"),("man","/nix/store/mnhlpg1k3v74ci2c0x1x5sjhfywcfgx4-hello-2.12.1-man"),("name","hello-2.12.1"),("out","/nix/store/la5dknhpig80236c7yp07hjjkkvc8q44-hello-2.12.1"),("postInstall","echo \"done\"\n\tsed -i 's/\\\\/\\//' $out\r"),("src","/nix/store/pa10z4ngm0g83kx9mssrqzz30s84vq7k-hello-2.12.1.tar.gz"),("system","x86_64-linux")])"#;
+  const HELLO_DRV: &str =
+    "/nix/store/80asiw467rdcvjwmaqafl8rsdi9nldzj-hello-2.12.1.drv";
+
+  /// A content addressed derivation depending on `HELLO`.
+  const GREETING: &str = r#"Derive([("out","","r:sha256","")],[("/nix/store/80asiw467rdcvjwmaqafl8rsdi9nldzj-hello-2.12.1.drv",["out"])],[],"x86_64-linux","/bin/sh",["-c","hello > $out"],[("builder","/bin/sh"),("hello","/nix/store/la5dknhpig80236c7yp07hjjkkvc8q44-hello-2.12.1"),("name","greeting"),("out","/1rz4g4znpzjwh1xymhjpm42vipw92pr73vdgl6xs1hycac8kf2n9"),("system","x86_64-linux")])"#;
+  const GREETING_DRV: &str =
+    "/nix/store/s1nr4c61yhzxxkwa902vgv5gv4i4mn8f-greeting.drv";
+
+  /// Computes the hashes of the outputs of the derivation at `path` modulo
+  /// fixed output derivations, reading the derivations from `derivations`.
+  fn hash_modulo(
+    derivations: &HashMap<&str, Derivation>,
+    path: &str,
+    mask_outputs: bool,
+  ) -> BTreeMap<String, String> {
+    derivations[path]
+      .hash_modulo(mask_outputs, &mut |input| {
+        Ok(hash_modulo(derivations, input, false))
+      })
+      .unwrap()
+  }
+
+  fn hashes(outputs: &[(&str, &str)]) -> BTreeMap<String, String> {
+    outputs
+      .iter()
+      .map(|&(name, hash)| (name.to_owned(), hash.to_owned()))
+      .collect()
+  }
+
+  // The expected hashes were computed with a separate implementation of the
+  // algorithms described in the Nix manual, which also produces the output
+  // paths of `FETCH` and `HELLO` and the well known placeholder of `out` in
+  // `GREETING`.
+  #[test]
+  fn derivation_hashes() {
+    let derivations = [
+      (FETCH_DRV, FETCH),
+      (HELLO_DRV, HELLO),
+      (GREETING_DRV, GREETING),
+    ]
+    .into_iter()
+    .map(|(path, aterm)| {
+      let derivation = aterm.parse::<Derivation>().unwrap();
+
+      assert_eq!(
+        derivation.unparse(&derivation.input_derivations, false),
+        aterm,
+        "derivations should be serialised byte for byte like Nix does",
+      );
+
+      (path, derivation)
+    })
+    .collect::<HashMap<_, _>>();
+
+    assert_eq!(
+      hash_modulo(&derivations, FETCH_DRV, true),
+      hashes(&[(
+        "out",
+        "796494f6dd0e991c3407b5bbcbbbcff794bd0cf6d2198a9ebd2c3f4099c43a90",
+      )]),
+    );
+    assert_eq!(
+      hash_modulo(&derivations, HELLO_DRV, true),
+      hashes(&[
+        (
+          "man",
+          "e3eaf0ebb2e4e657410301c21b82551895511b4f7e2f0f10ac935eb299d8a5f5",
+        ),
+        (
+          "out",
+          "e3eaf0ebb2e4e657410301c21b82551895511b4f7e2f0f10ac935eb299d8a5f5",
+        ),
+      ]),
+    );
+    assert_eq!(
+      hash_modulo(&derivations, HELLO_DRV, false),
+      hashes(&[
+        (
+          "man",
+          "001b63a46fcedebd0a13d61ac1b5a9784cbc87aa4f011a3904e5f33edb3d0fce",
+        ),
+        (
+          "out",
+          "001b63a46fcedebd0a13d61ac1b5a9784cbc87aa4f011a3904e5f33edb3d0fce",
+        ),
+      ]),
+    );
+    assert_eq!(
+      hash_modulo(&derivations, GREETING_DRV, true),
+      hashes(&[(
+        "out",
+        "1ac7a53f1468ee496ad348e6e20455bfb78f26f2d1abed9735a09f7d1e920065",
+      )]),
+    );
+  }
+
+  #[test]
+  fn derivation_diff() {
+    let old = OLD.parse::<Derivation>().unwrap();
+    let new = NEW.parse::<Derivation>().unwrap();
+
+    assert_eq!(
+      old.env["postInstall"], "echo \"done\"\nexit 0",
+      "escapes should be unescaped",
+    );
+    assert_eq!(old.input_derivations.len(), 2);
+    assert_eq!(old.unparse(&old.input_derivations, false), OLD);
+
+    let diff = DerivationDiff::from_derivations(
+      Diff {
+        old: store_path_with_hash('7', "curl-8.5.0.drv"),
+        new: store_path_with_hash('8', "curl-8.5.0.drv"),
+      },
+      &Diff { old, new },
+    );
+
+    assert_eq!(diff.input_derivations, Diff {
+      old: vec![
+        "/nix/store/1114yybkm5hnwjyxv129js3mjp7kbrax-openssl-3.0.13.drv"
+          .to_owned(),
+      ],
+      new: vec![
+        "/nix/store/6664yybkm5hnwjyxv129js3mjp7kbrax-openssl-3.0.14.drv"
+          .to_owned(),
+      ],
+    });
+    assert_eq!(diff.input_sources, Diff::default());
+    assert_eq!(diff.system, None);
+    assert_eq!(diff.builder, None);
+    assert_eq!(diff.args, None);
+    assert_eq!(diff.env, [
+      ("configureFlags".to_owned(), Diff {
+        old: Some("--with-openssl".to_owned()),
+        new: Some("--with-openssl --with-zstd".to_owned()),
+      }),
+      ("postInstall".to_owned(), Diff {
+        old: Some("echo \"done\"\nexit 0".to_owned()),
+        new: None,
+      }),
+      ("separateDebugInfo".to_owned(), Diff {
+        old: None,
+        new: Some("1".to_owned()),
+      }),
+    ]);
+
+    "Derive([".parse::<Derivation>().unwrap_err();
+  }
+
+  #[test]
+  fn main_outputs() {
+    let path = store_path_with_hash('7', "hello-2.12.1.drv");
+    let names = |names: &[&str]| {
+      names
+        .iter()
+        .map(|&name| name.to_owned())
+        .collect::<Vec<_>>()
+    };
+
+    assert_eq!(main_output(&names(&["man", "out"]), &path).unwrap(), "out");
+    assert_eq!(main_output(&names(&["bin"]), &path).unwrap(), "bin");
+    main_output(&names(&["bin", "man"]), &path).unwrap_err();
+    main_output(&names(&[]), &path).unwrap_err();
+  }
+}
@@ -1,18 +1,11 @@
 use std::{
   cmp,
   collections::{
+    BTreeMap,
     HashMap,
     HashSet,
   },
-  fmt::{
-    self,
-    Write as _,
-  },
-  fs,
-  path::{
-    Path,
-    PathBuf,
-  },
+  fmt::Write as _,
   thread,
 };
 
@@ -25,48 +18,120 @@ use itertools::{
   EitherOrBoth,
   Itertools,
 };
-use size::Size;
-use unicode_width::UnicodeWidthStr as _;
-use yansi::{
-  Paint as _,
-  Painted,
+use serde::{
+  Serialize,
+  Serializer,
 };
+use size::Size;
 
 use crate::{
+  ClosureSource,
+  RebuildCause,
+  SizeAttribution,
   StorePath,
   Version,
-  store,
 };
 
-#[derive(Debug, Default)]
-struct Diff<T> {
-  old: T,
-  new: T,
+/// A pair of values, one for the old and one for the new closure.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct Diff<T> {
+  /// The value for the old closure.
+  pub old: T,
+  /// The value for the new closure.
+  pub new: T,
+}
+
+/// Pairs the paths of `old` and `new` with the same key.
+///
+/// Keys with more than one path on either side are ambiguous, so their paths
+/// are left unpaired. If there is only a single unpaired path on both sides,
+/// those are paired as well, so version changes are covered. Other unpaired
+/// paths are skipped with a warning naming them as `kind`.
+pub(crate) fn pair_paths<K: Ord>(
+  old: BTreeMap<K, Vec<StorePath>>,
+  new: BTreeMap<K, Vec<StorePath>>,
+  kind: &str,
+) -> Vec<Diff<StorePath>> {
+  let mut pairs = Vec::new();
+  let mut unpaired = Diff::<Vec<StorePath>>::default();
+
+  for entry in old
+    .into_iter()
+    .merge_join_by(new, |this, that| this.0.cmp(&that.0))
+  {
+    match entry {
+      EitherOrBoth::Both((_, mut old), (_, mut new))
+        if old.len() == 1 && new.len() == 1 =>
+      {
+        pairs.push(Diff {
+          old: old.remove(0),
+          new: new.remove(0),
+        });
+      },
+      EitherOrBoth::Both((_, old), (_, new)) => {
+        unpaired.old.extend(old);
+        unpaired.new.extend(new);
+      },
+      EitherOrBoth::Left((_, old)) => unpaired.old.extend(old),
+      EitherOrBoth::Right((_, new)) => unpaired.new.extend(new),
+    }
+  }
+
+  if unpaired.old.len() == 1 && unpaired.new.len() == 1 {
+    pairs.push(Diff {
+      old: unpaired.old.remove(0),
+      new: unpaired.new.remove(0),
+    });
+  } else {
+    for path in unpaired.old.iter().chain(&unpaired.new) {
+      log::warn!(
+        "skipping {kind} '{path}' without a counterpart",
+        path = path.display()
+      );
+    }
+  }
+
+  pairs
 }
 
+/// How the versions of a package present in both closures changed.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-enum Change {
+pub enum Change {
+  /// Some versions were upgraded and others downgraded.
   UpgradeDowngrade,
+  /// The versions were upgraded.
   Upgraded,
+  /// The versions were downgraded.
   Downgraded,
 }
 
+/// Whether a package was changed, added or removed.
+///
+/// Ordered by category only, so all [`DiffStatus::Changed`] statuses compare
+/// equal regardless of their [`Change`].
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-enum DiffStatus {
+pub enum DiffStatus {
+  /// The package is in both closures, but with different versions.
   Changed(Change),
+  /// The package is only in the new closure.
   Added,
+  /// The package is only in the old closure.
   Removed,
+  /// The package is in both closures with the same versions, but at
+  /// different store paths, usually because one of its dependencies changed.
+  Rebuilt,
 }
 
-impl DiffStatus {
-  fn char(self) -> Painted<&'static char> {
-    match self {
-      Self::Changed(Change::UpgradeDowngrade) => 'C'.yellow().bold(),
-      Self::Changed(Change::Upgraded) => 'U'.bright_cyan().bold(),
-      Self::Changed(Change::Downgraded) => 'D'.magenta().bold(),
-      Self::Added => 'A'.green().bold(),
-      Self::Removed => 'R'.red().bold(),
-    }
+impl Serialize for DiffStatus {
+  fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(match *self {
+      Self::Changed(Change::UpgradeDowngrade) => "upgrade-downgrade",
+      Self::Changed(Change::Upgraded) => "upgraded",
+      Self::Changed(Change::Downgraded) => "downgraded",
+      Self::Added => "added",
+      Self::Removed => "removed",
+      Self::Rebuilt => "rebuilt",
+    })
   }
 }
 
@@ -81,6 +146,7 @@ impl cmp::Ord for DiffStatus {
     use DiffStatus::{
       Added,
       Changed,
+      Rebuilt,
       Removed,
     };
     #[expect(clippy::match_same_arms)]
@@ -88,20 +154,25 @@ impl cmp::Ord for DiffStatus {
       (Changed(_), Changed(_)) => cmp::Ordering::Equal,
       (Added, Added) => cmp::Ordering::Equal,
       (Removed, Removed) => cmp::Ordering::Equal,
+      (Rebuilt, Rebuilt) => cmp::Ordering::Equal,
 
       (Changed(_), _) => cmp::Ordering::Less,
       (_, Changed(_)) => cmp::Ordering::Greater,
 
-      (Added, Removed) => cmp::Ordering::Less,
-      (Removed, Added) => cmp::Ordering::Greater,
+      (Added, _) => cmp::Ordering::Less,
+      (_, Added) => cmp::Ordering::Greater,
+
+      (Removed, Rebuilt) => cmp::Ordering::Less,
+      (Rebuilt, Removed) => cmp::Ordering::Greater,
     }
   }
 }
 
 /// Documents if the derivation is a system package and if
 /// it was added / removed as such.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-enum DerivationSelectionStatus {
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum DerivationSelectionStatus {
   /// The derivation is a system package, status unchanged.
   Selected,
   /// The derivation was not a system package before but is now.
@@ -126,106 +197,276 @@ impl DerivationSelectionStatus {
     }
   }
 
-  fn char(self) -> Painted<&'static char> {
-    match self {
-      Self::Selected => '*'.bold(),
-      Self::NewlySelected => '+'.bold(),
-      Self::Unselected => Painted::new(&'.'),
-      Self::NewlyUnselected => Painted::new(&'-'),
-    }
+  /// Checks whether the derivation is a system package in either closure.
+  #[must_use]
+  pub fn is_selected(self) -> bool {
+    self != Self::Unselected
   }
 }
 
-/// Writes the diff header (<<< out, >>>in) and package diff.
-///
-/// # Returns
+/// The difference between two closures.
 ///
-/// Will return the amount of package diffs written. Even when zero,
-/// the header will be written.
-#[expect(clippy::missing_errors_doc)]
-pub fn write_paths_diffln(
-  writer: &mut impl fmt::Write,
-  path_old: &Path,
-  path_new: &Path,
-) -> Result<usize> {
-  let connection = store::connect()?;
-
-  let paths_old = connection
-    .query_dependents(path_old)
-    .with_context(|| {
-      format!(
-        "failed to query dependencies of path '{path}'",
-        path = path_old.display()
-      )
-    })?
-    .map(|(_, path)| path);
-
-  log::info!(
-    "found {count}+ packages in old closure",
-    count = paths_old.size_hint().0,
-  );
-
-  let paths_new = connection
-    .query_dependents(path_new)
-    .with_context(|| {
-      format!(
-        "failed to query dependencies of path '{path}'",
-        path = path_new.display()
-      )
-    })?
-    .map(|(_, path)| path);
-
-  let system_derivations_old = connection
-    .query_system_derivations(path_old)
-    .with_context(|| {
-      format!(
-        "failed to query system derivations of path '{path}",
-        path = path_old.display()
-      )
-    })?
-    .map(|(_, path)| path);
-
-  let system_derivations_new = connection
-    .query_system_derivations(path_new)
-    .with_context(|| {
-      format!(
-        "failed to query system derivations of path '{path}",
-        path = path_old.display()
-      )
-    })?
-    .map(|(_, path)| path);
-
-  log::info!(
-    "found {count}+ packages in new closure",
-    count = paths_new.size_hint().0,
-  );
-
-  writeln!(
-    writer,
-    "{arrows} {old}",
-    arrows = "<<<".bold(),
-    old = path_old.display(),
-  )?;
-  writeln!(
-    writer,
-    "{arrows} {new}",
-    arrows = ">>>".bold(),
-    new = fs::canonicalize(path_new)
-      .unwrap_or_else(|_| path_new.to_path_buf())
-      .display(),
-  )?;
-
-  writeln!(writer)?;
-
-  Ok(write_packages_diffln(
-    writer,
-    paths_old,
-    paths_new,
-    system_derivations_old,
-    system_derivations_new,
-  )?)
+/// This is what every renderer writes, but it can also be inspected
+/// directly by programs embedding dix.
+#[derive(Debug, Clone)]
+pub struct ClosureDiff {
+  /// The root paths of the closures.
+  pub paths:          Diff<StorePath>,
+  /// The total closure sizes.
+  pub sizes:          Diff<Size>,
+  /// The amount of paths in the closures.
+  pub path_counts:    Diff<usize>,
+  /// The summed up NAR size of the paths only in the old or new closure. The
+  /// new size is what has to be copied to switch to the new closure.
+  pub unique_sizes:   Diff<Size>,
+  /// Whether some paths in either closure are not built yet, see
+  /// [`ClosureDiff::mark_unbuilt`]. Their sizes are unknown, so the closure
+  /// sizes are left out of every output.
+  pub unbuilt:        bool,
+  /// The packages that differ, sorted by status and name.
+  pub packages:       Vec<PackageDiff>,
+  /// Which system packages the size difference is caused by, only set when
+  /// queried with [`SizeAttribution::query`].
+  pub attribution:    Option<SizeAttribution>,
+  /// The paths that caused other paths to be rebuilt, only set when queried
+  /// with [`RebuildCause::query`].
+  pub rebuild_causes: Option<Vec<RebuildCause>>,
+}
+
+impl ClosureDiff {
+  /// Queries the closures of `path_old` and `path_new` from `source_old` and
+  /// `source_new` respectively, which may be the same source, and diffs them.
+  ///
+  /// Unlike [`spawn_size_diff`], the closure sizes are queried on the current
+  /// thread after the packages.
+  #[expect(clippy::missing_errors_doc)]
+  pub fn query(
+    source_old: &dyn ClosureSource,
+    path_old: &StorePath,
+    source_new: &dyn ClosureSource,
+    path_new: &StorePath,
+  ) -> Result<Self> {
+    let mut diff =
+      Self::query_packages(source_old, path_old, source_new, path_new)?;
+
+    diff.sizes = Diff {
+      old: source_old.query_closure_size(path_old)?,
+      new: source_new.query_closure_size(path_new)?,
+    };
+
+    Ok(diff)
+  }
+
+  /// Like [`ClosureDiff::query`], but leaves the closure sizes at zero so they
+  /// can be computed in the background with [`spawn_size_diff`].
+  #[expect(clippy::missing_errors_doc)]
+  pub fn query_packages(
+    source_old: &dyn ClosureSource,
+    path_old: &StorePath,
+    source_new: &dyn ClosureSource,
+    path_new: &StorePath,
+  ) -> Result<Self> {
+    let paths_old =
+      source_old
+        .query_closure_nar_sizes(path_old)
+        .with_context(|| {
+          format!(
+            "failed to query dependencies of path '{path}'",
+            path = path_old.display()
+          )
+        })?;
+
+    log::info!(
+      "found {count} packages in old closure",
+      count = paths_old.len(),
+    );
+
+    let paths_new =
+      source_new
+        .query_closure_nar_sizes(path_new)
+        .with_context(|| {
+          format!(
+            "failed to query dependencies of path '{path}'",
+            path = path_new.display()
+          )
+        })?;
+
+    let system_derivations_old = source_old
+      .query_system_derivations(path_old)
+      .with_context(|| {
+        format!(
+          "failed to query system derivations of path '{path}",
+          path = path_old.display()
+        )
+      })?;
+
+    let system_derivations_new = source_new
+      .query_system_derivations(path_new)
+      .with_context(|| {
+        format!(
+          "failed to query system derivations of path '{path}",
+          path = path_new.display()
+        )
+      })?;
+
+    log::info!(
+      "found {count} packages in new closure",
+      count = paths_new.len(),
+    );
+
+    let unique_size = |paths: &[(StorePath, Size)],
+                       other: &[(StorePath, Size)]| {
+      let other = other.iter().map(|entry| &entry.0).collect::<HashSet<_>>();
+
+      paths
+        .iter()
+        .filter(|entry| !other.contains(&entry.0))
+        .fold(Size::from_bytes(0_u64), |sum, &(_, nar_size)| {
+          sum + nar_size
+        })
+    };
+
+    let unique_sizes = Diff {
+      old: unique_size(&paths_old, &paths_new),
+      new: unique_size(&paths_new, &paths_old),
+    };
+
+    Ok(Self {
+      paths: Diff {
+        old: path_old.clone(),
+        new: path_new.clone(),
+      },
+      sizes: Diff::default(),
+      path_counts: Diff {
+        old: paths_old.len(),
+        new: paths_new.len(),
+      },
+      unique_sizes,
+      unbuilt: false,
+      packages: diff_packages(
+        paths_old.into_iter(),
+        paths_new.into_iter(),
+        system_derivations_old.into_iter(),
+        system_derivations_new.into_iter(),
+      ),
+      attribution: None,
+      rebuild_causes: None,
+    })
+  }
+
+  /// Marks the packages with any of the `unbuilt` paths in the old or new
+  /// closure as not built yet, like the ones of a
+  /// [`DerivationClosure`](crate::DerivationClosure).
+  ///
+  /// Unbuilt paths count as zero bytes, so the sizes of the marked packages
+  /// and, if any path is unbuilt, of the closures are left out of every
+  /// output.
+  pub fn mark_unbuilt(&mut self, unbuilt: Diff<&[StorePath]>) {
+    fn names(paths: &[StorePath]) -> HashSet<&str> {
+      paths
+        .iter()
+        .filter_map(|path| path.parse_name_and_version().ok())
+        .map(|(name, _)| name)
+        .collect()
+    }
+
+    let names = Diff {
+      old: names(unbuilt.old),
+      new: names(unbuilt.new),
+    };
+
+    self.unbuilt = !unbuilt.old.is_empty() || !unbuilt.new.is_empty();
+
+    for package in &mut self.packages {
+      let name = package.name.as_str();
+
+      package.unbuilt = (package.status != DiffStatus::Removed
+        && names.new.contains(name))
+        || (package.status != DiffStatus::Added && names.old.contains(name));
+    }
+  }
+
+  /// Counts the packages of every status.
+  ///
+  /// Only the packages still in [`Self::packages`] are counted, so after
+  /// filtering them with a [`PackageFilter`](crate::PackageFilter) the summary
+  /// only covers the packages that were kept.
+  #[must_use]
+  pub fn summary(&self) -> Summary {
+    let mut summary = Summary {
+      paths: self.path_counts,
+      ..Summary::default()
+    };
+
+    for package in &self.packages {
+      let count = match package.status {
+        DiffStatus::Changed(Change::Upgraded) => &mut summary.upgraded,
+        DiffStatus::Changed(Change::Downgraded) => &mut summary.downgraded,
+        DiffStatus::Changed(Change::UpgradeDowngrade) => {
+          &mut summary.upgrade_downgrade
+        },
+        DiffStatus::Added => &mut summary.added,
+        DiffStatus::Removed => &mut summary.removed,
+        DiffStatus::Rebuilt => &mut summary.rebuilt,
+      };
+
+      count.packages += 1;
+      if package.selection.is_selected() {
+        count.selected += 1;
+      }
+    }
+
+    summary
+  }
+}
+
+/// The amount of packages with a single status.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct StatusCount {
+  /// The amount of packages.
+  pub packages: usize,
+  /// How many of the packages are system packages in either closure.
+  pub selected: usize,
+}
+
+/// Statistics about a [`ClosureDiff`], see [`ClosureDiff::summary`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct Summary {
+  /// Packages whose versions were upgraded.
+  pub upgraded:          StatusCount,
+  /// Packages whose versions were downgraded.
+  pub downgraded:        StatusCount,
+  /// Packages with both upgraded and downgraded versions.
+  pub upgrade_downgrade: StatusCount,
+  /// Packages only in the new closure.
+  pub added:             StatusCount,
+  /// Packages only in the old closure.
+  pub removed:           StatusCount,
+  /// Packages whose versions stayed the same, but whose paths changed.
+  pub rebuilt:           StatusCount,
+  /// The amount of paths in the closures.
+  pub paths:             Diff<usize>,
 }
 
+/// The difference of a single package between two closures.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackageDiff {
+  /// The name of the package, without the hash and version.
+  pub name:      String,
+  /// The versions only found in the old or new closure. For rebuilt
+  /// packages, these are the versions found in both.
+  pub versions:  Diff<Vec<Version>>,
+  /// The summed up NAR size of all paths of the package in the old and new
+  /// closure, including versions found in both.
+  pub sizes:     Diff<Size>,
+  /// Whether the package was changed, added or removed.
+  pub status:    DiffStatus,
+  /// Whether the package is a system package in either closure.
+  pub selection: DerivationSelectionStatus,
+  /// Whether some paths of the package are not built yet, so its sizes are
+  /// unknown, see [`ClosureDiff::mark_unbuilt`].
+  pub unbuilt:   bool,
+}
 /// Takes a list of versions which may contain duplicates and deduplicates it by
 /// replacing multiple occurrences of an element with the same element plus the
 /// amount it occurs.
@@ -278,15 +519,33 @@ fn deduplicate_versions(versions: &mut Vec<Version>) {
   *versions = deduplicated;
 }
 
-#[expect(clippy::cognitive_complexity, clippy::too_many_lines)]
-fn write_packages_diffln(
-  writer: &mut impl fmt::Write,
-  paths_old: impl Iterator<Item = StorePath>,
-  paths_new: impl Iterator<Item = StorePath>,
+/// Splits a version produced by [`deduplicate_versions`] into the version,
+/// which is `None` for paths without one, and the amount of times it occurs.
+///
+/// Store path names can't contain spaces or `<`, so neither the count suffix
+/// nor `<none>` can be part of a parsed version.
+pub(crate) fn split_version_count(version: &Version) -> (Option<&str>, usize) {
+  let (version, count) = version
+    .rsplit_once(" ×")
+    .and_then(|(version, count)| Some((version, count.parse().ok()?)))
+    .unwrap_or((version.as_str(), 1));
+
+  ((version != "<none>").then_some(version), count)
+}
+
+/// Diffs the packages of two closures by name, sorted by status and name.
+///
+/// Packages whose paths didn't change are left out.
+fn diff_packages(
+  paths_old: impl Iterator<Item = (StorePath, Size)>,
+  paths_new: impl Iterator<Item = (StorePath, Size)>,
   system_paths_old: impl Iterator<Item = StorePath>,
   system_paths_new: impl Iterator<Item = StorePath>,
-) -> Result<usize, fmt::Error> {
-  let mut paths = HashMap::<String, Diff<Vec<Version>>>::new();
+) -> Vec<PackageDiff> {
+  let mut packages = HashMap::<
+    String,
+    (Diff<Vec<Version>>, Diff<Size>, Diff<Vec<StorePath>>),
+  >::new();
 
   // Collect the names of old and new paths.
   let system_derivations_old: HashSet<String> = system_paths_old
@@ -313,17 +572,20 @@ fn write_packages_diffln(
     })
     .collect();
 
-  for path in paths_old {
+  for (path, nar_size) in paths_old {
     match path.parse_name_and_version() {
       Ok((name, version)) => {
         log::debug!("parsed name: {name}");
         log::debug!("parsed version: {version:?}");
 
-        paths
-          .entry(name.into())
-          .or_default()
+        let &mut (ref mut versions, ref mut sizes, ref mut paths) =
+          packages.entry(name.into()).or_default();
+
+        versions
           .old
           .push(version.unwrap_or_else(|| Version::from("<none>".to_owned())));
+        sizes.old += nar_size;
+        paths.old.push(path.clone());
       },
 
       Err(error) => {
@@ -332,17 +594,20 @@ fn write_packages_diffln(
     }
   }
 
-  for path in paths_new {
+  for (path, nar_size) in paths_new {
     match path.parse_name_and_version() {
       Ok((name, version)) => {
         log::debug!("parsed name: {name}");
         log::debug!("parsed version: {version:?}");
 
-        paths
-          .entry(name.into())
-          .or_default()
+        let &mut (ref mut versions, ref mut sizes, ref mut paths) =
+          packages.entry(name.into()).or_default();
+
+        versions
           .new
           .push(version.unwrap_or_else(|| Version::from("<none>".to_owned())));
+        sizes.new += nar_size;
+        paths.new.push(path.clone());
       },
 
       Err(error) => {
@@ -350,9 +615,9 @@ fn write_packages_diffln(
       },
     }
   }
-  let mut diffs = paths
+  let mut diffs = packages
     .into_iter()
-    .filter_map(|(name, mut versions)| {
+    .filter_map(|(name, (mut versions, sizes, mut paths))| {
       deduplicate_versions(&mut versions.old);
       deduplicate_versions(&mut versions.new);
 
@@ -363,7 +628,21 @@ fn write_packages_diffln(
       versions.new.retain(|ver| !old_copy.contains(ver));
 
       let status = match (versions.old.len(), versions.new.len()) {
-        (0, 0) => return None,
+        (0, 0) => {
+          paths.old.sort();
+          paths.new.sort();
+
+          if paths.old == paths.new {
+            return None;
+          }
+
+          versions = Diff {
+            old: old_copy,
+            new: new_copy,
+          };
+
+          DiffStatus::Rebuilt
+        },
         (0, _) => DiffStatus::Added,
         (_, 0) => DiffStatus::Removed,
         _ => {
@@ -406,285 +685,46 @@ fn write_packages_diffln(
         &system_derivations_new,
       );
 
-      Some((name, versions, status, selection))
+      Some(PackageDiff {
+        name,
+        versions,
+        sizes,
+        status,
+        selection,
+        unbuilt: false,
+      })
     })
     .collect::<Vec<_>>();
 
-  diffs.sort_by(
-    |&(ref a_name, _, a_status, _), &(ref b_name, _, b_status, _)| {
-      a_status.cmp(&b_status).then_with(|| a_name.cmp(b_name))
-    },
-  );
-
-  #[expect(clippy::pattern_type_mismatch)]
-  let name_width = diffs
-    .iter()
-    .map(|(name, ..)| name.width())
-    .max()
-    .unwrap_or(0);
-
-  let mut last_status = None::<DiffStatus>;
-
-  for &(ref name, ref versions, status, selection) in &diffs {
-    if last_status.is_none_or(|last_status| {
-      // Using the Ord implementation instead of Eq on purpose.
-      // Eq returns false for DiffStatus::Changed(X) == DiffStatus::Changed(Y).
-      last_status.cmp(&status) != cmp::Ordering::Equal
-    }) {
-      writeln!(
-        writer,
-        "{nl}{status}",
-        nl = if last_status.is_some() { "\n" } else { "" },
-        status = match status {
-          DiffStatus::Changed(_) => "CHANGED",
-          DiffStatus::Added => "ADDED",
-          DiffStatus::Removed => "REMOVED",
-        }
-        .bold(),
-      )?;
-
-      last_status = Some(status);
-    }
-
-    let status = status.char();
-    let selection = selection.char();
-    let name = name.paint(selection.style);
-
-    write!(writer, "[{status}{selection}] {name:<name_width$}")?;
-
-    let mut oldacc = String::new();
-    let mut oldwrote = false;
-    let mut newacc = String::new();
-    let mut newwrote = false;
-
-    for diff in Itertools::zip_longest(versions.old.iter(), versions.new.iter())
-    {
-      match diff {
-        EitherOrBoth::Left(old_version) => {
-          if oldwrote {
-            write!(oldacc, ", ")?;
-          } else {
-            write!(oldacc, " ")?;
-            oldwrote = true;
-          }
-
-          for old_comp in old_version {
-            match old_comp {
-              Ok(old_comp) => write!(oldacc, "{old}", old = old_comp.red())?,
-              Err(ignored) => write!(oldacc, "{ignored}")?,
-            }
-          }
-        },
-
-        EitherOrBoth::Right(new_version) => {
-          if newwrote {
-            write!(newacc, ", ")?;
-          } else {
-            write!(newacc, " ")?;
-            newwrote = true;
-          }
-
-          for new_comp in new_version {
-            match new_comp {
-              Ok(new_comp) => write!(newacc, "{new}", new = new_comp.green())?,
-              Err(ignored) => write!(newacc, "{ignored}")?,
-            }
-          }
-        },
-
-        EitherOrBoth::Both(old_version, new_version) => {
-          if old_version == new_version {
-            continue;
-          }
-
-          if oldwrote {
-            write!(oldacc, ", ")?;
-          } else {
-            write!(oldacc, " ")?;
-            oldwrote = true;
-          }
-          if newwrote {
-            write!(newacc, ", ")?;
-          } else {
-            write!(newacc, " ")?;
-            newwrote = true;
-          }
-
-          let mut old_version: Vec<_> = old_version.into_iter().collect();
-          let mut new_version: Vec<_> = new_version.into_iter().collect();
-
-          let last_same = old_version.last() == new_version.last();
-          let last = (last_same).then(|| {
-            old_version.pop();
-            new_version.pop().unwrap()
-          });
-
-          for diff in Itertools::zip_longest(
-            old_version.into_iter(),
-            new_version.into_iter(),
-          ) {
-            match diff {
-              EitherOrBoth::Left(old_comp) => {
-                match old_comp {
-                  Ok(old_comp) => {
-                    write!(oldacc, "{old}", old = old_comp.red())?;
-                  },
-                  Err(ignored) => {
-                    write!(oldacc, "{ignored}")?;
-                  },
-                }
-              },
-
-              EitherOrBoth::Right(new_comp) => {
-                match new_comp {
-                  Ok(new_comp) => {
-                    write!(newacc, "{new}", new = new_comp.green())?;
-                  },
-                  Err(ignored) => {
-                    write!(newacc, "{ignored}")?;
-                  },
-                }
-              },
-
-              EitherOrBoth::Both(old_comp, new_comp) => {
-                match (old_comp, new_comp) {
-                  (Ok(old_comp), Ok(new_comp)) => {
-                    let mut difference_started = false;
-                    let is_hash = is_hash(&old_comp);
-
-                    for char in diff::chars(*old_comp, *new_comp) {
-                      match char {
-                        diff::Result::Both(old_part, new_part) => {
-                          if difference_started {
-                            difference_started = true;
-                            write!(oldacc, "{old}", old = old_part.red())?;
-                            write!(newacc, "{new}", new = new_part.green())?;
-                          } else {
-                            write!(oldacc, "{old}", old = old_part.yellow())?;
-                            write!(newacc, "{new}", new = new_part.yellow())?;
-                          }
-                        },
-                        diff::Result::Left(old_part) => {
-                          difference_started = is_hash;
-                          write!(oldacc, "{old}", old = old_part.red())?;
-                        },
-                        diff::Result::Right(new_part) => {
-                          difference_started = is_hash;
-                          write!(newacc, "{new}", new = new_part.green())?;
-                        },
-                      }
-                    }
-                  },
-                  (old_comp, new_comp) => {
-                    match old_comp {
-                      Ok(old_comp) => {
-                        write!(oldacc, "{old}", old = old_comp.red())?;
-                      },
-                      Err(old_comp) => write!(oldacc, "{old_comp}")?,
-                    }
-
-                    match new_comp {
-                      Ok(new_comp) => {
-                        write!(newacc, "{new}", new = new_comp.green())?;
-                      },
-                      Err(new_comp) => write!(newacc, "{new_comp}")?,
-                    }
-                  },
-                }
-              },
-            }
-          }
-          if last_same {
-            if let Some(Ok(last)) = last {
-              write!(oldacc, "{old}", old = last.yellow())?;
-              write!(newacc, "{new}", new = last.yellow())?;
-            }
-          }
-        },
-      }
-    }
-
-    write!(
-      writer,
-      "{oldacc}{arrow}{newacc}",
-      arrow = if !oldacc.is_empty() && !newacc.is_empty() {
-        " ->"
-      } else {
-        ""
-      }
-    )?;
-
-    writeln!(writer)?;
-  }
+  diffs.sort_by(|this, that| {
+    this
+      .status
+      .cmp(&that.status)
+      .then_with(|| this.name.cmp(&that.name))
+  });
 
-  Ok(diffs.len())
+  diffs
 }
-
-/// Spawns a task to compute the data required by [`write_size_diffln`].
+/// Spawns a task to compute the closure sizes of a [`ClosureDiff`].
+///
+/// `open` is called on the spawned thread and returns the sources of the old
+/// and new closure, since most sources can't be shared between threads.
 #[must_use]
 pub fn spawn_size_diff(
-  path_old: PathBuf,
-  path_new: PathBuf,
+  open: impl FnOnce() -> Result<(Box<dyn ClosureSource>, Box<dyn ClosureSource>)>
+  + Send
+  + 'static,
+  path_old: StorePath,
+  path_new: StorePath,
 ) -> thread::JoinHandle<Result<(Size, Size)>> {
   log::debug!("calculating closure sizes in background");
 
   thread::spawn(move || {
-    let connection = store::connect()?;
+    let (source_old, source_new) = open()?;
 
     Ok::<_, Error>((
-      connection.query_closure_size(&path_old)?,
-      connection.query_closure_size(&path_new)?,
+      source_old.query_closure_size(&path_old)?,
+      source_new.query_closure_size(&path_new)?,
     ))
   })
 }
-
-/// Writes the size difference between two numbers to `writer`.
-///
-/// # Returns
-///
-/// Will return nothing when successful.
-///
-/// # Errors
-///
-/// Returns `Err` when writing to `writer` fails.
-pub fn write_size_diffln(
-  writer: &mut impl fmt::Write,
-  size_old: Size,
-  size_new: Size,
-) -> fmt::Result {
-  let size_diff = size_new - size_old;
-
-  writeln!(
-    writer,
-    "{header}: {size_old} -> {size_new}",
-    header = "SIZE".bold(),
-    size_old = size_old.red(),
-    size_new = size_new.green(),
-  )?;
-
-  writeln!(
-    writer,
-    "{header}: {size_diff}",
-    header = "DIFF".bold(),
-    size_diff = if size_diff.bytes() > 0 {
-      size_diff.green()
-    } else {
-      size_diff.red()
-    },
-  )
-}
-
-#[expect(clippy::cast_precision_loss)]
-fn dissimilar_score(input: &str) -> f64 {
-  input
-    .chars()
-    .chunk_by(char::is_ascii_digit)
-    .into_iter()
-    .map(|(_, chunk)| (2.1_f64).powf(chunk.count() as f64))
-    .sum::<f64>()
-    / input.chars().count() as f64
-}
-
-fn is_hash(input: &str) -> bool {
-  dissimilar_score(input) < 70.0
-}
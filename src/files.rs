@@ -0,0 +1,591 @@
+use std::{
+  collections::{
+    BTreeMap,
+    HashSet,
+  },
+  fs,
+  io,
+  os::unix::fs::PermissionsExt as _,
+  path::{
+    Path,
+    PathBuf,
+  },
+};
+
+use anyhow::{
+  Context as _,
+  Result,
+  bail,
+};
+use itertools::{
+  EitherOrBoth,
+  Itertools as _,
+};
+use sha2::{
+  Digest as _,
+  Sha256,
+};
+use size::Size;
+
+use crate::{
+  ClosureSource,
+  Diff,
+  PathInfo,
+  StoreDirs,
+  StorePath,
+  derivation::hex,
+  diff::pair_paths,
+};
+
+/// A file, symlink or directory inside a store path.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FileEntry {
+  /// A regular file.
+  File {
+    /// The size of the file.
+    size:       Size,
+    /// Whether the file is executable.
+    executable: bool,
+    /// The SHA-256 hash of the contents, encoded in hexadecimal.
+    ///
+    /// Only computed for files that are the same size in both store paths,
+    /// since files of different sizes differ anyway.
+    hash:       Option<String>,
+  },
+  /// A symlink.
+  Symlink {
+    /// Where the symlink points to.
+    target: PathBuf,
+  },
+  /// A directory.
+  Directory,
+}
+
+impl FileEntry {
+  /// Reads the entry at `path` without following symlinks.
+  fn read(path: &Path) -> Result<Self> {
+    let metadata = fs::symlink_metadata(path).with_context(|| {
+      format!("failed to read metadata of '{path}'", path = path.display())
+    })?;
+
+    Ok(if metadata.is_symlink() {
+      Self::Symlink {
+        target: fs::read_link(path).with_context(|| {
+          format!("failed to read symlink '{path}'", path = path.display())
+        })?,
+      }
+    } else if metadata.is_dir() {
+      Self::Directory
+    } else {
+      Self::File {
+        size:       Size::from_bytes(metadata.len()),
+        executable: metadata.permissions().mode() & 0o111 != 0,
+        hash:       None,
+      }
+    })
+  }
+
+  /// Returns the size of the entry if it is a regular file.
+  #[must_use]
+  pub fn file_size(&self) -> Option<Size> {
+    match *self {
+      Self::File { size, .. } => Some(size),
+      Self::Symlink { .. } | Self::Directory => None,
+    }
+  }
+
+  /// Hashes the contents of `path` if the entry is a regular file.
+  fn hash(&mut self, path: &Path) -> Result<()> {
+    if let Self::File { ref mut hash, .. } = *self {
+      let mut file = fs::File::open(path).with_context(|| {
+        format!("failed to open file '{path}'", path = path.display())
+      })?;
+
+      let mut hasher = Sha256::new();
+      io::copy(&mut file, &mut hasher).with_context(|| {
+        format!("failed to hash file '{path}'", path = path.display())
+      })?;
+
+      *hash = Some(hex(&hasher.finalize()));
+    }
+
+    Ok(())
+  }
+}
+
+/// A path inside a store path that was added, removed or modified.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileChange {
+  /// The path relative to the store path, which is empty for the store path
+  /// itself.
+  pub path:    PathBuf,
+  /// The old and new entries, with `None` if the path only exists in the
+  /// other store path.
+  pub entries: Diff<Option<FileEntry>>,
+}
+
+/// What differs between the files of two store paths of a package.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FilesDiff {
+  /// The store paths.
+  pub paths:   Diff<StorePath>,
+  /// The changed paths inside the store paths, sorted by path.
+  pub changes: Vec<FileChange>,
+}
+
+/// Returns `relative` inside of `root`, or `root` itself if it is empty.
+fn join(root: &Path, relative: &Path) -> PathBuf {
+  if relative.as_os_str().is_empty() {
+    root.to_path_buf()
+  } else {
+    root.join(relative)
+  }
+}
+
+/// Reads every entry inside `root` and `root` itself, by their paths relative
+/// to `root`.
+fn read_tree(root: &Path) -> Result<BTreeMap<PathBuf, FileEntry>> {
+  let mut entries = BTreeMap::new();
+  let mut queue = vec![PathBuf::new()];
+
+  while let Some(relative) = queue.pop() {
+    let path = join(root, &relative);
+    let entry = FileEntry::read(&path)?;
+
+    if entry == FileEntry::Directory {
+      for child in fs::read_dir(&path).with_context(|| {
+        format!("failed to read directory '{path}'", path = path.display())
+      })? {
+        let child = child.with_context(|| {
+          format!("failed to read directory '{path}'", path = path.display())
+        })?;
+
+        queue.push(relative.join(child.file_name()));
+      }
+    }
+
+    entries.insert(relative, entry);
+  }
+
+  Ok(entries)
+}
+
+/// Returns the output the store path of a package with `version` likely is
+/// of, guessed from the end of the version, like `man` for
+/// `firefox-121.0-man`.
+///
+/// Versions ending in something like `-rc1` are mistaken for outputs, so
+/// this is only a fallback for paths whose deriver is unknown.
+fn output_name(version: Option<&str>) -> &str {
+  version
+    .and_then(|version| version.rsplit_once('-'))
+    .map(|(_, suffix)| suffix)
+    .filter(|suffix| !suffix.starts_with(|char: char| char.is_ascii_digit()))
+    .unwrap_or("out")
+}
+
+/// Returns the paths of `package` in `infos` that are not in `other`, by the
+/// output they are of.
+///
+/// The output is looked up in the deriver of the path if `source` knows it,
+/// and guessed from the version otherwise.
+fn package_paths(
+  source: &dyn ClosureSource,
+  infos: &[PathInfo],
+  other: &HashSet<&StorePath>,
+  package: &str,
+) -> BTreeMap<String, Vec<StorePath>> {
+  let mut paths = BTreeMap::<_, Vec<_>>::new();
+
+  for info in infos {
+    if other.contains(&info.path) {
+      continue;
+    }
+
+    let Ok((name, version)) = info.path.parse_name_and_version() else {
+      continue;
+    };
+
+    if name != package {
+      continue;
+    }
+
+    let output = info
+      .deriver
+      .as_ref()
+      .and_then(|deriver| source.query_derivation_outputs(deriver).ok())
+      .and_then(|outputs| {
+        outputs
+          .into_iter()
+          .find(|output| output.1.as_ref() == Some(&info.path))
+          .map(|output| output.0)
+      })
+      .unwrap_or_else(|| {
+        output_name(version.as_deref().map(String::as_str)).to_owned()
+      });
+
+    paths.entry(output).or_default().push(info.path.clone());
+  }
+
+  for (output, paths) in &paths {
+    if paths.len() > 1 {
+      log::warn!(
+        "package '{package}' has {count} paths of output '{output}' that \
+         can't be told apart",
+        count = paths.len(),
+      );
+    }
+  }
+
+  paths
+}
+
+impl FilesDiff {
+  /// Queries the closures of `path_old` and `path_new` from `source_old` and
+  /// `source_new` respectively, and diffs the files of the paths of `package`
+  /// that are only in one of them.
+  ///
+  /// Paths are paired by output, which is looked up from their deriver, or
+  /// guessed from the end of their version if the sources don't know it.
+  /// Outputs with several paths on one side are left unpaired. If there is
+  /// only a single unpaired path on both sides, those are paired as well. The
+  /// paths have to be in the store of `dirs`.
+  #[expect(clippy::missing_errors_doc)]
+  pub fn query(
+    source_old: &dyn ClosureSource,
+    path_old: &StorePath,
+    source_new: &dyn ClosureSource,
+    path_new: &StorePath,
+    package: &str,
+    dirs: &StoreDirs,
+  ) -> Result<Vec<Self>> {
+    let query = |source: &dyn ClosureSource, path: &StorePath| {
+      source.query_closure_infos(path).with_context(|| {
+        format!(
+          "failed to query dependencies of path '{path}'",
+          path = path.display()
+        )
+      })
+    };
+
+    let infos_old = query(source_old, path_old)?;
+    let infos_new = query(source_new, path_new)?;
+
+    let paths_old = infos_old
+      .iter()
+      .map(|info| &info.path)
+      .collect::<HashSet<_>>();
+    let paths_new = infos_new
+      .iter()
+      .map(|info| &info.path)
+      .collect::<HashSet<_>>();
+
+    let pairs = pair_paths(
+      package_paths(source_old, &infos_old, &paths_new, package),
+      package_paths(source_new, &infos_new, &paths_old, package),
+      "path",
+    );
+
+    if pairs.is_empty() {
+      bail!("package '{package}' has no changed paths to compare");
+    }
+
+    pairs
+      .into_iter()
+      .map(|paths| Self::from_paths(paths, dirs))
+      .collect()
+  }
+
+  /// Compares the files of the store paths at `paths`, which have to be in
+  /// the store of `dirs`.
+  ///
+  /// Files are compared by type, size and whether they are executable, and
+  /// by the hash of their contents if they are the same size. Symlinks are
+  /// compared by target.
+  #[expect(clippy::missing_errors_doc)]
+  pub fn from_paths(paths: Diff<StorePath>, dirs: &StoreDirs) -> Result<Self> {
+    let roots = Diff {
+      old: dirs.physical(&paths.old),
+      new: dirs.physical(&paths.new),
+    };
+
+    for (path, root) in [(&paths.old, &roots.old), (&paths.new, &roots.new)] {
+      if fs::symlink_metadata(root).is_err() {
+        bail!(
+          "path '{path}' is not in the local store",
+          path = path.display()
+        );
+      }
+    }
+
+    let entries_old = read_tree(&roots.old)?;
+    let entries_new = read_tree(&roots.new)?;
+
+    let mut changes = Vec::new();
+
+    for entry in entries_old
+      .into_iter()
+      .merge_join_by(entries_new, |this, that| this.0.cmp(&that.0))
+    {
+      let (path, mut old, mut new) = match entry {
+        EitherOrBoth::Both((path, old), (_, new)) => {
+          (path, Some(old), Some(new))
+        },
+        EitherOrBoth::Left((path, old)) => (path, Some(old), None),
+        EitherOrBoth::Right((path, new)) => (path, None, Some(new)),
+      };
+
+      if let (Some(old), Some(new)) = (old.as_mut(), new.as_mut())
+        && old.file_size().is_some()
+        && old.file_size() == new.file_size()
+      {
+        old.hash(&join(&roots.old, &path))?;
+        new.hash(&join(&roots.new, &path))?;
+      }
+
+      if old != new {
+        changes.push(FileChange {
+          path,
+          entries: Diff { old, new },
+        });
+      }
+    }
+
+    log::info!(
+      "found {count} changed files between '{old}' and '{new}'",
+      count = changes.len(),
+      old = paths.old.display(),
+      new = paths.new.display(),
+    );
+
+    Ok(Self { paths, changes })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{
+    collections::{
+      BTreeMap,
+      HashSet,
+    },
+    fs,
+    os::unix::fs::symlink,
+    path::PathBuf,
+  };
+
+  use anyhow::{
+    Result,
+    bail,
+  };
+  use size::Size;
+  use tempfile::TempDir;
+
+  use super::{
+    FileChange,
+    FileEntry,
+    FilesDiff,
+    output_name,
+    package_paths,
+  };
+  use crate::{
+    ClosureSource,
+    Diff,
+    PathInfo,
+    StoreDirs,
+    StorePath,
+    test_utils::{
+      store_path,
+      store_path_with_hash,
+    },
+  };
+
+  /// Creates a store mounted in a temporary directory, like an installer
+  /// target, holding two paths of hello.
+  fn mounted_store() -> (TempDir, StoreDirs, Diff<StorePath>) {
+    let root = tempfile::tempdir().unwrap();
+    let dirs = StoreDirs {
+      root: root.path().to_path_buf(),
+      ..StoreDirs::default()
+    };
+    let paths = Diff {
+      old: store_path("hello-2.12"),
+      new: store_path_with_hash('1', "hello-2.13"),
+    };
+
+    fs::create_dir_all(dirs.physical(&paths.old)).unwrap();
+    fs::create_dir_all(dirs.physical(&paths.new)).unwrap();
+
+    (root, dirs, paths)
+  }
+
+  fn file(size: u64, hash: Option<&str>) -> FileEntry {
+    FileEntry::File {
+      size:       Size::from_bytes(size),
+      executable: false,
+      hash:       hash.map(str::to_owned),
+    }
+  }
+
+  #[test]
+  fn files_of_the_same_size_are_hashed() {
+    let (_root, dirs, paths) = mounted_store();
+    let old = dirs.physical(&paths.old);
+    let new = dirs.physical(&paths.new);
+
+    fs::write(old.join("version"), "2.12").unwrap();
+    fs::write(new.join("version"), "2.13").unwrap();
+    fs::write(old.join("hello"), "hello").unwrap();
+    fs::write(new.join("hello"), "hello").unwrap();
+
+    assert_eq!(FilesDiff::from_paths(paths, &dirs).unwrap().changes, [
+      FileChange {
+        path:    PathBuf::from("version"),
+        entries: Diff {
+          old: Some(file(
+            4,
+            Some(
+              "f487eb5226f64295c10fa7fcd3aba777dd763d2a0d2e58d9e2e43cf5f3023d0c"
+            ),
+          )),
+          new: Some(file(
+            4,
+            Some(
+              "ca2d9f9187ee627e5ba4d407fa5ab02fa3e8e8bc0801f8ed87a7bf40332c1013"
+            ),
+          )),
+        },
+      }
+    ]);
+  }
+
+  #[test]
+  fn files_added_and_removed() {
+    let (_root, dirs, paths) = mounted_store();
+
+    fs::write(dirs.physical(&paths.old).join("README"), "hello").unwrap();
+    fs::create_dir_all(dirs.physical(&paths.new).join("share")).unwrap();
+
+    assert_eq!(FilesDiff::from_paths(paths, &dirs).unwrap().changes, [
+      FileChange {
+        path:    PathBuf::from("README"),
+        entries: Diff {
+          old: Some(file(5, None)),
+          new: None,
+        },
+      },
+      FileChange {
+        path:    PathBuf::from("share"),
+        entries: Diff {
+          old: None,
+          new: Some(FileEntry::Directory),
+        },
+      },
+    ]);
+  }
+
+  #[test]
+  fn files_symlinks() {
+    let (_root, dirs, paths) = mounted_store();
+
+    // Symlinks into the store are compared by target, not followed.
+    symlink("/nix/store/2.12", dirs.physical(&paths.old).join("lib")).unwrap();
+    symlink("/nix/store/2.13", dirs.physical(&paths.new).join("lib")).unwrap();
+
+    assert_eq!(FilesDiff::from_paths(paths, &dirs).unwrap().changes, [
+      FileChange {
+        path:    PathBuf::from("lib"),
+        entries: Diff {
+          old: Some(FileEntry::Symlink {
+            target: PathBuf::from("/nix/store/2.12"),
+          }),
+          new: Some(FileEntry::Symlink {
+            target: PathBuf::from("/nix/store/2.13"),
+          }),
+        },
+      }
+    ]);
+  }
+
+  #[test]
+  fn files_of_missing_path() {
+    let (_root, dirs, paths) = mounted_store();
+
+    FilesDiff::from_paths(
+      Diff {
+        old: paths.old,
+        new: store_path_with_hash('2', "hello-2.14"),
+      },
+      &dirs,
+    )
+    .unwrap_err();
+  }
+
+  #[test]
+  fn output_names() {
+    assert_eq!(output_name(Some("121.0-man")), "man");
+    assert_eq!(output_name(Some("2.40-66")), "out");
+    assert_eq!(output_name(None), "out");
+  }
+
+  /// A source that only knows the outputs of derivations.
+  struct Outputs(Vec<(StorePath, &'static str, StorePath)>);
+
+  impl ClosureSource for Outputs {
+    fn query_path_info(&self, path: &StorePath) -> Result<PathInfo> {
+      bail!("unknown path '{path}'", path = path.display())
+    }
+
+    fn query_derivation_outputs(
+      &self,
+      path: &StorePath,
+    ) -> Result<BTreeMap<String, Option<StorePath>>> {
+      Ok(
+        self
+          .0
+          .iter()
+          .filter(|output| output.0 == *path)
+          .map(|&(_, name, ref output)| (name.to_owned(), Some(output.clone())))
+          .collect(),
+      )
+    }
+  }
+
+  fn info(path: &StorePath, deriver: Option<&StorePath>) -> PathInfo {
+    PathInfo {
+      path:       path.clone(),
+      references: Vec::new(),
+      nar_size:   Size::from_bytes(1_u64),
+      deriver:    deriver.cloned(),
+    }
+  }
+
+  #[test]
+  fn package_paths_by_output() {
+    let derivation = store_path("foo-1.0-rc1.drv");
+    let foo = store_path("foo-1.0-rc1");
+    let foo_man = store_path("foo-1.0-rc1-man");
+    let foo_old = store_path("foo-0.9");
+    let foo_other = store_path_with_hash('1', "foo-0.9");
+    let bar = store_path("bar-1.0");
+
+    let source = Outputs(vec![(derivation.clone(), "out", foo.clone())]);
+    let infos = [
+      info(&foo, Some(&derivation)),
+      info(&foo_man, None),
+      info(&foo_old, None),
+      info(&foo_other, None),
+      info(&bar, None),
+    ];
+
+    assert_eq!(
+      package_paths(&source, &infos, &HashSet::from([&foo_old]), "foo"),
+      BTreeMap::from([
+        // The deriver knows it is not an output named `rc1`, and paths of
+        // the same output are kept instead of replacing each other.
+        ("out".to_owned(), vec![foo, foo_other]),
+        ("man".to_owned(), vec![foo_man]),
+      ]),
+    );
+  }
+}
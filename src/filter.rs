@@ -0,0 +1,368 @@
+use std::str;
+
+use anyhow::{
+  Error,
+  Result,
+  bail,
+};
+use regex::Regex;
+use size::Size;
+
+use crate::{
+  Change,
+  DiffStatus,
+  PackageDiff,
+};
+
+/// A kind of package difference to keep when filtering.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatusFilter {
+  /// Packages with any version change.
+  Changed,
+  /// Packages with an upgraded version, including those that also have a
+  /// downgraded one.
+  Upgraded,
+  /// Packages with a downgraded version, including those that also have an
+  /// upgraded one.
+  Downgraded,
+  /// Packages only in the new closure.
+  Added,
+  /// Packages only in the old closure.
+  Removed,
+  /// Packages rebuilt without a version change.
+  Rebuilt,
+}
+
+impl str::FromStr for StatusFilter {
+  type Err = Error;
+
+  fn from_str(status: &str) -> Result<Self> {
+    Ok(match status {
+      "changed" => Self::Changed,
+      "upgraded" => Self::Upgraded,
+      "downgraded" => Self::Downgraded,
+      "added" => Self::Added,
+      "removed" => Self::Removed,
+      "rebuilt" => Self::Rebuilt,
+      _ => {
+        bail!(
+          "invalid status '{status}', expected one of changed, upgraded, \
+           downgraded, added, removed or rebuilt"
+        )
+      },
+    })
+  }
+}
+
+impl StatusFilter {
+  /// Checks whether a package with `status` is kept by this filter.
+  #[must_use]
+  pub fn matches(self, status: DiffStatus) -> bool {
+    matches!(
+      (self, status),
+      (Self::Changed, DiffStatus::Changed(_))
+        | (
+          Self::Upgraded,
+          DiffStatus::Changed(Change::Upgraded | Change::UpgradeDowngrade),
+        )
+        | (
+          Self::Downgraded,
+          DiffStatus::Changed(Change::Downgraded | Change::UpgradeDowngrade),
+        )
+        | (Self::Added, DiffStatus::Added)
+        | (Self::Removed, DiffStatus::Removed)
+        | (Self::Rebuilt, DiffStatus::Rebuilt)
+    )
+  }
+}
+
+/// Decides which packages of a diff are kept, the rest is left out of every
+/// output.
+///
+/// Rebuilt packages are left out unless asked for with
+/// [`PackageFilter::rebuilt`] or [`StatusFilter::Rebuilt`], since a single
+/// changed dependency can rebuild most of a closure.
+#[derive(Debug, Clone, Default)]
+pub struct PackageFilter {
+  /// Only keep packages matching any of these statuses. Every status is kept
+  /// if empty.
+  pub statuses:        Vec<StatusFilter>,
+  /// Only keep packages that are system packages in either closure.
+  pub selected_only:   bool,
+  /// Only keep packages whose name matches any of these patterns. Every name
+  /// is kept if empty.
+  pub include:         Vec<Regex>,
+  /// Leave out packages whose name matches any of these patterns.
+  pub exclude:         Vec<Regex>,
+  /// Only keep packages whose size grew or shrank by at least this much.
+  pub min_size_change: Option<Size>,
+  /// Keep rebuilt packages.
+  pub rebuilt:         bool,
+}
+
+impl PackageFilter {
+  /// Checks whether `package` is kept by this filter.
+  #[must_use]
+  pub fn matches(&self, package: &PackageDiff) -> bool {
+    (package.status != DiffStatus::Rebuilt
+      || self.rebuilt
+      || self.statuses.contains(&StatusFilter::Rebuilt))
+      && (self.statuses.is_empty()
+        || self
+          .statuses
+          .iter()
+          .any(|status| status.matches(package.status)))
+      && (!self.selected_only || package.selection.is_selected())
+      && (self.include.is_empty()
+        || self
+          .include
+          .iter()
+          .any(|pattern| pattern.is_match(&package.name)))
+      && !self
+        .exclude
+        .iter()
+        .any(|pattern| pattern.is_match(&package.name))
+      && self.min_size_change.is_none_or(|min_size_change| {
+        (package.sizes.new - package.sizes.old).bytes().abs()
+          >= min_size_change.bytes()
+      })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use regex::Regex;
+  use size::Size;
+
+  use super::{
+    PackageFilter,
+    StatusFilter,
+  };
+  use crate::{
+    Change,
+    DerivationSelectionStatus,
+    Diff,
+    DiffStatus,
+    PackageDiff,
+  };
+
+  fn package(
+    name: &str,
+    status: DiffStatus,
+    selection: DerivationSelectionStatus,
+    size_change: u64,
+  ) -> PackageDiff {
+    PackageDiff {
+      name: name.to_owned(),
+      versions: Diff::default(),
+      sizes: Diff {
+        old: Size::from_bytes(1000_u64),
+        new: Size::from_bytes(1000 + size_change),
+      },
+      status,
+      selection,
+      unbuilt: false,
+    }
+  }
+
+  fn names<'a>(
+    filter: &PackageFilter,
+    packages: &'a [PackageDiff],
+  ) -> Vec<&'a str> {
+    packages
+      .iter()
+      .filter(|package| filter.matches(package))
+      .map(|package| package.name.as_str())
+      .collect()
+  }
+
+  #[test]
+  fn filter_statuses() {
+    let packages = [
+      package(
+        "firefox",
+        DiffStatus::Changed(Change::UpgradeDowngrade),
+        DerivationSelectionStatus::Unselected,
+        0,
+      ),
+      package(
+        "curl",
+        DiffStatus::Changed(Change::Upgraded),
+        DerivationSelectionStatus::Unselected,
+        0,
+      ),
+      package(
+        "vim",
+        DiffStatus::Removed,
+        DerivationSelectionStatus::Unselected,
+        0,
+      ),
+    ];
+
+    assert_eq!(names(&PackageFilter::default(), &packages), [
+      "firefox", "curl", "vim"
+    ]);
+    assert_eq!(
+      names(
+        &PackageFilter {
+          statuses: vec!["downgraded".parse().unwrap()],
+          ..PackageFilter::default()
+        },
+        &packages,
+      ),
+      ["firefox"],
+    );
+    assert_eq!(
+      names(
+        &PackageFilter {
+          statuses: vec![StatusFilter::Upgraded, StatusFilter::Removed],
+          ..PackageFilter::default()
+        },
+        &packages,
+      ),
+      ["firefox", "curl", "vim"],
+    );
+
+    "updated".parse::<StatusFilter>().unwrap_err();
+  }
+
+  #[test]
+  fn filter_rebuilt() {
+    let packages = [
+      package(
+        "firefox",
+        DiffStatus::Changed(Change::Upgraded),
+        DerivationSelectionStatus::Unselected,
+        0,
+      ),
+      package(
+        "curl",
+        DiffStatus::Rebuilt,
+        DerivationSelectionStatus::Unselected,
+        0,
+      ),
+    ];
+
+    assert_eq!(names(&PackageFilter::default(), &packages), ["firefox"]);
+    assert_eq!(
+      names(
+        &PackageFilter {
+          rebuilt: true,
+          ..PackageFilter::default()
+        },
+        &packages,
+      ),
+      ["firefox", "curl"],
+    );
+    assert_eq!(
+      names(
+        &PackageFilter {
+          statuses: vec![StatusFilter::Rebuilt],
+          ..PackageFilter::default()
+        },
+        &packages,
+      ),
+      ["curl"],
+    );
+  }
+
+  #[test]
+  fn filter_selected_only() {
+    let packages = [
+      package(
+        "firefox",
+        DiffStatus::Added,
+        DerivationSelectionStatus::NewlySelected,
+        0,
+      ),
+      package(
+        "vim",
+        DiffStatus::Removed,
+        DerivationSelectionStatus::NewlyUnselected,
+        0,
+      ),
+      package(
+        "curl",
+        DiffStatus::Added,
+        DerivationSelectionStatus::Unselected,
+        0,
+      ),
+    ];
+
+    assert_eq!(
+      names(
+        &PackageFilter {
+          selected_only: true,
+          ..PackageFilter::default()
+        },
+        &packages,
+      ),
+      ["firefox", "vim"],
+    );
+  }
+
+  #[test]
+  fn filter_names() {
+    let packages = [
+      package(
+        "firefox",
+        DiffStatus::Added,
+        DerivationSelectionStatus::Unselected,
+        0,
+      ),
+      package(
+        "firefox-unwrapped",
+        DiffStatus::Added,
+        DerivationSelectionStatus::Unselected,
+        0,
+      ),
+      package(
+        "vim",
+        DiffStatus::Added,
+        DerivationSelectionStatus::Unselected,
+        0,
+      ),
+    ];
+
+    assert_eq!(
+      names(
+        &PackageFilter {
+          include: vec![Regex::new("^firefox").unwrap()],
+          exclude: vec![Regex::new("-unwrapped$").unwrap()],
+          ..PackageFilter::default()
+        },
+        &packages,
+      ),
+      ["firefox"],
+    );
+  }
+
+  #[test]
+  fn filter_size_change() {
+    let packages = [
+      package(
+        "firefox",
+        DiffStatus::Changed(Change::Upgraded),
+        DerivationSelectionStatus::Unselected,
+        100,
+      ),
+      package(
+        "curl",
+        DiffStatus::Changed(Change::Upgraded),
+        DerivationSelectionStatus::Unselected,
+        99,
+      ),
+    ];
+
+    assert_eq!(
+      names(
+        &PackageFilter {
+          min_size_change: Some(Size::from_bytes(100_u64)),
+          ..PackageFilter::default()
+        },
+        &packages,
+      ),
+      ["firefox"],
+    );
+  }
+}
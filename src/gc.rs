@@ -0,0 +1,351 @@
+use std::{
+  collections::HashSet,
+  fs,
+  path::{
+    Path,
+    PathBuf,
+  },
+};
+
+use anyhow::{
+  Context as _,
+  Result,
+};
+use size::Size;
+
+use crate::{
+  ClosureSource,
+  StoreDirs,
+  StorePath,
+};
+
+/// A GC root, which is a symlink keeping a store path alive.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct GcRoot {
+  /// The symlink, with every directory leading up to it resolved so the same
+  /// link reached through different directories compares equal.
+  pub link: PathBuf,
+  /// The store path the symlink resolves to.
+  pub path: StorePath,
+}
+
+impl GcRoot {
+  /// Resolves the root at `link` to the store path it points to, which has
+  /// to be inside the store directory of `dirs`.
+  #[expect(clippy::missing_errors_doc)]
+  pub fn read(link: &Path, dirs: &StoreDirs) -> Result<Self> {
+    Ok(Self {
+      link: resolve_parent(link, dirs)?,
+      path: StorePath::canonicalize_in(link, dirs)?,
+    })
+  }
+}
+
+/// Resolves every directory leading up to `path`, but not `path` itself.
+fn resolve_parent(path: &Path, dirs: &StoreDirs) -> Result<PathBuf> {
+  let (Some(parent), Some(name)) = (path.parent(), path.file_name()) else {
+    return Ok(path.to_path_buf());
+  };
+
+  let parent = if parent.as_os_str().is_empty() {
+    Path::new(".")
+  } else {
+    parent
+  };
+
+  Ok(dirs.canonicalize(parent)?.join(name))
+}
+
+/// How much store space deleting a generation would reclaim, given the GC
+/// roots that remain afterwards.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GcImpact {
+  /// The generation that would be deleted.
+  pub generation:  StorePath,
+  /// The amount of remaining GC roots.
+  pub roots:       usize,
+  /// The amount of paths in the closure of the generation.
+  pub path_count:  usize,
+  /// The closure size of the generation.
+  pub size:        Size,
+  /// The paths of the closure that no remaining root depends on, biggest
+  /// first.
+  pub reclaimable: Vec<(StorePath, Size)>,
+}
+
+impl GcImpact {
+  /// Queries the closure of `generation` and every one of `roots` from
+  /// `source`, and finds the paths only `generation` depends on.
+  ///
+  /// The root with the same link as `generation` is ignored, since it is the
+  /// one being deleted. Other roots pointing to the same store path, like
+  /// another profile link to the current generation, keep it alive.
+  #[expect(clippy::missing_errors_doc)]
+  pub fn query(
+    source: &dyn ClosureSource,
+    generation: &GcRoot,
+    roots: &[GcRoot],
+  ) -> Result<Self> {
+    let closure = source
+      .query_closure_nar_sizes(&generation.path)
+      .with_context(|| {
+        format!(
+          "failed to query dependencies of path '{path}'",
+          path = generation.path.display()
+        )
+      })?;
+
+    let mut reachable = HashSet::new();
+    let mut root_count = 0;
+
+    for root in roots.iter().filter(|root| root.link != generation.link) {
+      root_count += 1;
+
+      reachable.extend(source.query_closure(&root.path).with_context(
+        || {
+          format!(
+            "failed to query dependencies of root '{path}'",
+            path = root.link.display()
+          )
+        },
+      )?);
+    }
+
+    log::info!(
+      "found {count} paths reachable from {root_count} roots",
+      count = reachable.len(),
+    );
+
+    let path_count = closure.len();
+    let size = closure
+      .iter()
+      .fold(Size::from_bytes(0_u64), |sum, &(_, nar_size)| {
+        sum + nar_size
+      });
+
+    let mut reclaimable = closure
+      .into_iter()
+      .filter(|entry| !reachable.contains(&entry.0))
+      .collect::<Vec<_>>();
+    reclaimable.sort_by(|this, that| {
+      that.1.cmp(&this.1).then_with(|| this.0.cmp(&that.0))
+    });
+
+    Ok(Self {
+      generation: generation.path.clone(),
+      roots: root_count,
+      path_count,
+      size,
+      reclaimable,
+    })
+  }
+
+  /// Returns the summed up NAR size of the reclaimable paths.
+  #[must_use]
+  pub fn reclaimable_size(&self) -> Size {
+    self
+      .reclaimable
+      .iter()
+      .fold(Size::from_bytes(0_u64), |sum, &(_, nar_size)| {
+        sum + nar_size
+      })
+  }
+}
+
+/// Returns the store path `path` is in, which may also be a file inside of
+/// it. `path` is where it is found on the filesystem.
+fn containing_store_path(path: &Path, dirs: &StoreDirs) -> Option<StorePath> {
+  let path = dirs.logical(path)?;
+  let name = path.strip_prefix(&dirs.store).ok()?.components().next()?;
+
+  StorePath::try_from_in(dirs.store.join(name), &dirs.store).ok()
+}
+
+/// Finds the symlinks in the `gcroots` directory of the Nix state directory
+/// and its subdirectories that point to store paths, like the garbage
+/// collector does.
+///
+/// Symlinks to directories outside of the store, such as the profiles
+/// directory, are followed. Dangling symlinks and those pointing to other
+/// files are skipped.
+#[expect(clippy::missing_errors_doc)]
+pub fn find_gc_roots(dirs: &StoreDirs) -> Result<Vec<GcRoot>> {
+  let mut roots = Vec::new();
+  let mut seen = HashSet::<PathBuf>::new();
+  let mut pending = vec![dirs.gc_roots()];
+
+  while let Some(dir) = pending.pop() {
+    let entries = fs::read_dir(&dir).with_context(|| {
+      format!("failed to read GC roots in '{dir}'", dir = dir.display())
+    })?;
+
+    for entry in entries {
+      let path = entry
+        .with_context(|| {
+          format!("failed to read GC roots in '{dir}'", dir = dir.display())
+        })?
+        .path();
+
+      let Ok(canonical) = dirs.canonicalize(&path) else {
+        log::debug!(
+          "skipping dangling GC root '{path}'",
+          path = path.display()
+        );
+        continue;
+      };
+
+      if let Some(root) = containing_store_path(&canonical, dirs) {
+        roots.push(GcRoot {
+          link: resolve_parent(&path, dirs)?,
+          path: root,
+        });
+      } else if canonical.is_dir() && seen.insert(canonical.clone()) {
+        pending.push(canonical);
+      }
+    }
+  }
+
+  roots.sort();
+  roots.dedup();
+
+  Ok(roots)
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{
+    fs,
+    os::unix::fs::symlink,
+    path::PathBuf,
+  };
+
+  use size::Size;
+
+  use super::{
+    GcImpact,
+    GcRoot,
+    find_gc_roots,
+  };
+  use crate::{
+    Dump,
+    PathInfo,
+    StoreDirs,
+    StorePath,
+    test_utils::store_path,
+  };
+
+  fn info(
+    path: &StorePath,
+    references: &[&StorePath],
+    nar_size: u64,
+  ) -> PathInfo {
+    PathInfo {
+      path:       path.clone(),
+      references: references.iter().copied().cloned().collect(),
+      nar_size:   Size::from_bytes(nar_size),
+      deriver:    None,
+    }
+  }
+
+  fn root(link: &str, path: &StorePath) -> GcRoot {
+    GcRoot {
+      link: PathBuf::from(format!("/nix/var/nix/profiles/{link}")),
+      path: path.clone(),
+    }
+  }
+
+  #[test]
+  fn gc_impact() {
+    let system_68 = store_path("system-68");
+    let system_69 = store_path("system-69");
+    let firefox_old = store_path("firefox-120.0");
+    let firefox_new = store_path("firefox-121.0");
+    let glibc = store_path("glibc-2.40");
+
+    let source = Dump::from_iter([
+      info(&system_68, &[&firefox_old, &glibc], 1),
+      info(&system_69, &[&firefox_new, &glibc], 1),
+      info(&firefox_old, &[&glibc], 100),
+      info(&firefox_new, &[&glibc], 200),
+      info(&glibc, &[], 10),
+    ]);
+
+    let generation = root("system-68-link", &system_68);
+
+    let impact = GcImpact::query(&source, &generation, &[
+      generation.clone(),
+      root("system-69-link", &system_69),
+    ])
+    .unwrap();
+
+    assert_eq!(impact.roots, 1);
+    assert_eq!(impact.path_count, 3);
+    assert_eq!(impact.size, Size::from_bytes(111_u64));
+    assert_eq!(impact.reclaimable, [
+      (firefox_old, Size::from_bytes(100_u64)),
+      (system_68, Size::from_bytes(1_u64)),
+    ]);
+    assert_eq!(impact.reclaimable_size(), Size::from_bytes(101_u64));
+  }
+
+  #[test]
+  fn gc_impact_of_generation_linked_twice() {
+    let system_68 = store_path("system-68");
+    let firefox = store_path("firefox-120.0");
+
+    let source = Dump::from_iter([
+      info(&system_68, &[&firefox], 1),
+      info(&firefox, &[], 100),
+    ]);
+
+    let generation = root("system-68-link", &system_68);
+
+    // Another link to the same generation, like the current system, keeps it
+    // alive.
+    let impact = GcImpact::query(&source, &generation, &[
+      generation.clone(),
+      root("system", &system_68),
+    ])
+    .unwrap();
+
+    assert_eq!(impact.roots, 1);
+    assert_eq!(impact.reclaimable, []);
+  }
+
+  #[test]
+  fn gc_roots() {
+    let root = tempfile::tempdir().unwrap();
+    let dirs = StoreDirs {
+      root: root.path().to_path_buf(),
+      ..StoreDirs::default()
+    };
+
+    let system = store_path("nixos-system");
+    let result = store_path("hello-2.12");
+    fs::create_dir_all(dirs.physical(&system)).unwrap();
+    fs::create_dir_all(dirs.physical(&result).join("bin")).unwrap();
+
+    let profiles = dirs.physical(&dirs.state.join("profiles"));
+    let auto = dirs.gc_roots().join("auto");
+    fs::create_dir_all(&profiles).unwrap();
+    fs::create_dir_all(&auto).unwrap();
+
+    // Profiles are found through the link to their directory.
+    symlink(&*system, profiles.join("system-1-link")).unwrap();
+    symlink("/nix/var/nix/profiles", dirs.gc_roots().join("profiles")).unwrap();
+    // Links to files inside of a store path keep the whole path alive.
+    symlink(result.join("bin"), auto.join("result")).unwrap();
+    symlink("/nix/store/gone", auto.join("dangling")).unwrap();
+
+    assert_eq!(find_gc_roots(&dirs).unwrap(), [
+      GcRoot {
+        link: auto.canonicalize().unwrap().join("result"),
+        path: result,
+      },
+      GcRoot {
+        link: profiles.canonicalize().unwrap().join("system-1-link"),
+        path: system,
+      },
+    ]);
+  }
+}
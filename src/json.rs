@@ -0,0 +1,184 @@
+use std::{
+  collections::HashMap,
+  path::{
+    Path,
+    PathBuf,
+  },
+};
+
+use anyhow::{
+  Context as _,
+  Result,
+  bail,
+};
+use serde::Deserialize;
+use size::Size;
+
+use crate::{
+  Dump,
+  PathInfo,
+  StorePath,
+};
+
+/// A single path info as printed by `nix path-info --json`.
+///
+/// The store path is only contained in the info itself in the list format
+/// used before Nix 2.19, newer versions print an object keyed by path.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct PathInfoJson {
+  path:       Option<PathBuf>,
+  #[serde(default = "default_valid")]
+  valid:      bool,
+  #[serde(default)]
+  nar_size:   u64,
+  #[serde(default)]
+  references: Vec<PathBuf>,
+  deriver:    Option<PathBuf>,
+}
+
+fn default_valid() -> bool {
+  true
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum PathInfosJson {
+  List(Vec<PathInfoJson>),
+  Map(HashMap<PathBuf, Option<PathInfoJson>>),
+}
+
+impl Dump {
+  /// Parses the output of `nix path-info --json --recursive`.
+  ///
+  /// Both the list format of older Nix versions and the object format of
+  /// newer ones are accepted. Invalid paths are skipped.
+  pub fn from_path_info_json(json: &str, store_dir: &Path) -> Result<Self> {
+    let infos: PathInfosJson =
+      serde_json::from_str(json).context("failed to parse path info JSON")?;
+
+    let infos: Vec<(PathBuf, PathInfoJson)> = match infos {
+      PathInfosJson::List(infos) => {
+        infos
+          .into_iter()
+          .map(|info| {
+            let Some(path) = info.path.clone() else {
+              bail!("path info is missing the 'path' field");
+            };
+            Ok((path, info))
+          })
+          .collect::<Result<_>>()?
+      },
+
+      PathInfosJson::Map(infos) => {
+        infos
+          .into_iter()
+          .filter_map(|(path, info)| Some((path, info?)))
+          .collect()
+      },
+    };
+
+    // Newer Nix versions may print base names instead of full paths.
+    let store_path =
+      |path: PathBuf| StorePath::try_from_in(store_dir.join(path), store_dir);
+
+    infos
+      .into_iter()
+      .filter(|entry| entry.1.valid)
+      .map(|(path, info)| {
+        Ok(PathInfo {
+          path:       store_path(path)?,
+          references: info
+            .references
+            .into_iter()
+            .map(store_path)
+            .collect::<Result<_>>()?,
+          nar_size:   Size::from_bytes(info.nar_size),
+          deriver:    info.deriver.map(store_path).transpose()?,
+        })
+      })
+      .collect()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::path::Path;
+
+  use size::Size;
+
+  use crate::{
+    ClosureSource as _,
+    Dump,
+    test_utils::{
+      store_path,
+      store_path_with_hash,
+    },
+  };
+
+  #[test]
+  fn path_info_list() {
+    let dump = Dump::from_path_info_json(
+      r#"[
+        {
+          "path": "/nix/store/0004yybkm5hnwjyxv129js3mjp7kbrax-hello-2.12.1",
+          "narHash": "sha256-0000000000000000000000000000000000000000000=",
+          "narSize": 226560,
+          "references": [
+            "/nix/store/1114yybkm5hnwjyxv129js3mjp7kbrax-glibc-2.40-66",
+            "/nix/store/0004yybkm5hnwjyxv129js3mjp7kbrax-hello-2.12.1"
+          ],
+          "deriver": "/nix/store/2224yybkm5hnwjyxv129js3mjp7kbrax-hello-2.12.1.drv"
+        },
+        {
+          "path": "/nix/store/1114yybkm5hnwjyxv129js3mjp7kbrax-glibc-2.40-66",
+          "narSize": 30000000,
+          "references": []
+        },
+        {
+          "path": "/nix/store/3334yybkm5hnwjyxv129js3mjp7kbrax-gone",
+          "valid": false
+        }
+      ]"#,
+      Path::new("/nix/store"),
+    )
+    .unwrap();
+
+    let hello = store_path("hello-2.12.1");
+
+    assert_eq!(dump.root(None).unwrap(), hello);
+    assert_eq!(
+      dump.query_closure_size(&hello).unwrap(),
+      Size::from_bytes(30_226_560_u64),
+    );
+    assert_eq!(
+      dump.query_path_info(&hello).unwrap().deriver,
+      Some(store_path_with_hash('2', "hello-2.12.1.drv")),
+    );
+  }
+
+  #[test]
+  fn path_info_map() {
+    let dump = Dump::from_path_info_json(
+      r#"{
+        "/nix/store/0004yybkm5hnwjyxv129js3mjp7kbrax-hello-2.12.1": {
+          "narSize": 226560,
+          "references": ["1114yybkm5hnwjyxv129js3mjp7kbrax-glibc-2.40-66"],
+          "deriver": null
+        },
+        "/nix/store/1114yybkm5hnwjyxv129js3mjp7kbrax-glibc-2.40-66": {
+          "narSize": 30000000,
+          "references": []
+        },
+        "/nix/store/3334yybkm5hnwjyxv129js3mjp7kbrax-gone": null
+      }"#,
+      Path::new("/nix/store"),
+    )
+    .unwrap();
+
+    let hello = store_path("hello-2.12.1");
+
+    assert_eq!(dump.root(None).unwrap(), hello);
+    assert_eq!(dump.query_closure(&hello).unwrap().len(), 2);
+  }
+}
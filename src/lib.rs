@@ -1,5 +1,10 @@
 use std::{
-  path::PathBuf,
+  fs,
+  path::{
+    self,
+    Path,
+    PathBuf,
+  },
   sync,
 };
 
@@ -12,49 +17,317 @@ use anyhow::{
 };
 use derive_more::Deref;
 
+mod attribution;
+pub use attribution::{
+  PackageAttribution,
+  SizeAttribution,
+};
+
+mod closure;
+pub use closure::{
+  ClosureSource,
+  Dump,
+  PathInfo,
+};
+
+mod daemon;
+pub use daemon::{
+  DaemonConnection,
+  connect_daemon,
+};
+
+mod derivation;
+pub use derivation::{
+  Derivation,
+  DerivationClosure,
+  DerivationDiff,
+  main_output,
+};
+
 mod diff;
 pub use diff::{
+  Change,
+  ClosureDiff,
+  DerivationSelectionStatus,
+  Diff,
+  DiffStatus,
+  PackageDiff,
+  StatusCount,
+  Summary,
   spawn_size_diff,
+};
+
+mod files;
+pub use files::{
+  FileChange,
+  FileEntry,
+  FilesDiff,
+};
+
+mod filter;
+pub use filter::{
+  PackageFilter,
+  StatusFilter,
+};
+
+mod gc;
+pub use gc::{
+  GcImpact,
+  GcRoot,
+  find_gc_roots,
+};
+
+mod json;
+
+mod narinfo;
+pub use narinfo::BinaryCache;
+
+mod rebuild;
+pub use rebuild::RebuildCause;
+
+mod registration;
+
+pub mod render;
+pub use render::{
   write_paths_diffln,
   write_size_diffln,
 };
 
+mod sort;
+pub use sort::SortOrder;
+
 mod store;
+pub use store::{
+  Connection,
+  connect,
+};
+
+#[cfg(test)] mod test_utils;
 
 mod version;
-use version::Version;
+pub use version::{
+  Version,
+  VersionComponent,
+  VersionComponentIter,
+};
+
+/// The default Nix store directory.
+pub const DEFAULT_STORE_DIR: &str = "/nix/store";
+
+/// The default Nix state directory.
+pub const DEFAULT_STATE_DIR: &str = "/nix/var/nix";
+
+/// The default directory the store and state directories are mounted under.
+pub const DEFAULT_ROOT_DIR: &str = "/";
+
+/// The most symlinks [`StoreDirs::canonicalize`] follows, like Linux does.
+const MAX_SYMLINKS: usize = 40;
+
+/// The directories a Nix store is made up of.
+///
+/// The store and state directories are the ones the store was built for,
+/// which the database and the symlinks into the store refer to. The store may
+/// be mounted somewhere else, like under `/mnt` for the target of an
+/// installer, in which case they are found inside the root directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoreDirs {
+  /// The directory all store paths live in, `/nix/store` by default.
+  pub store: PathBuf,
+  /// The directory containing the Nix database, `/nix/var/nix` by default.
+  pub state: PathBuf,
+  /// The directory the store and state directories are mounted under, `/` by
+  /// default.
+  pub root:  PathBuf,
+}
+
+impl Default for StoreDirs {
+  fn default() -> Self {
+    Self {
+      store: PathBuf::from(DEFAULT_STORE_DIR),
+      state: PathBuf::from(DEFAULT_STATE_DIR),
+      root:  PathBuf::from(DEFAULT_ROOT_DIR),
+    }
+  }
+}
+
+impl StoreDirs {
+  /// Returns the path of the `SQLite` database inside the state directory.
+  #[must_use]
+  pub fn database(&self) -> PathBuf {
+    self.physical(&self.state.join("db").join("db.sqlite"))
+  }
+
+  /// Returns the path of the socket the Nix daemon listens on inside the
+  /// state directory.
+  #[must_use]
+  pub fn daemon_socket(&self) -> PathBuf {
+    self.physical(&self.state.join("daemon-socket").join("socket"))
+  }
+
+  /// Returns the directory containing the symlinks the garbage collector
+  /// treats as roots inside the state directory.
+  #[must_use]
+  pub fn gc_roots(&self) -> PathBuf {
+    self.physical(&self.state.join("gcroots"))
+  }
+
+  /// Returns where `path`, like a store path, is found on the filesystem,
+  /// which is inside the root directory.
+  #[must_use]
+  pub fn physical(&self, path: &Path) -> PathBuf {
+    self.root.join(path.strip_prefix("/").unwrap_or(path))
+  }
+
+  /// Returns the path `path` inside the root directory refers to, like
+  /// `/nix/store/...` for `/mnt/nix/store/...`, or `None` if it is outside
+  /// of the root directory.
+  #[must_use]
+  pub fn logical(&self, path: &Path) -> Option<PathBuf> {
+    let relative = path.strip_prefix(&self.root).ok().or_else(|| {
+      // Canonicalized paths start with the canonicalized root directory.
+      path.strip_prefix(self.root.canonicalize().ok()?).ok()
+    })?;
+
+    Some(Path::new("/").join(relative))
+  }
+
+  /// Resolves the symlinks in `path` like [`Path::canonicalize`].
+  ///
+  /// Inside the root directory, symlinks are resolved as if it was the root
+  /// of the filesystem, so a profile pointing to `/nix/store/...` resolves to
+  /// the store path inside the root directory rather than the one of the
+  /// running system.
+  #[expect(clippy::missing_errors_doc)]
+  pub fn canonicalize(&self, path: &Path) -> Result<PathBuf> {
+    let error = || {
+      format!(
+        "failed to canonicalize path '{path}'",
+        path = path.display(),
+      )
+    };
+
+    if self.root == Path::new(DEFAULT_ROOT_DIR) {
+      return path.canonicalize().with_context(error);
+    }
+
+    let root = self.root.canonicalize().with_context(|| {
+      format!(
+        "failed to canonicalize root directory '{root}'",
+        root = self.root.display(),
+      )
+    })?;
+
+    let absolute = path::absolute(path).with_context(error)?;
+    let Ok(relative) = absolute.strip_prefix(&root) else {
+      return absolute.canonicalize().with_context(error);
+    };
+
+    // The components left to resolve, last one first.
+    let mut pending = relative
+      .components()
+      .rev()
+      .map(|component| component.as_os_str().to_owned())
+      .collect::<Vec<_>>();
+    let mut resolved = PathBuf::from("/");
+    let mut symlinks = 0_usize;
+
+    while let Some(component) = pending.pop() {
+      if component == ".." {
+        resolved.pop();
+        continue;
+      }
+      if component == "." || component == "/" {
+        continue;
+      }
+
+      let candidate = resolved.join(&component);
+
+      let Ok(target) = fs::read_link(root.join(candidate.strip_prefix("/")?))
+      else {
+        resolved = candidate;
+        continue;
+      };
+
+      symlinks += 1;
+      if symlinks > MAX_SYMLINKS {
+        return Err(anyhow!("too many levels of symlinks")).with_context(error);
+      }
+
+      if target.is_absolute() {
+        resolved = PathBuf::from("/");
+      }
+
+      pending.extend(
+        target
+          .components()
+          .rev()
+          .map(|component| component.as_os_str().to_owned()),
+      );
+    }
+
+    let canonical = root.join(resolved.strip_prefix("/")?);
+    fs::symlink_metadata(&canonical).with_context(error)?;
+
+    Ok(canonical)
+  }
+}
 
 #[derive(Deref, Debug, Clone, Copy, PartialEq, Eq, Hash)]
 struct DerivationId(i64);
 
-/// A validated store path. Always starts with `/nix/store`.
+/// A validated store path. Always starts with the store directory.
 ///
-/// Can be created using `StorePath::try_from(path_buf)`.
-#[derive(Deref, Debug, Clone, PartialEq, Eq, Hash)]
+/// Can be created using `StorePath::try_from(path_buf)` for the default store
+/// directory, or `StorePath::try_from_in(path_buf, store_dir)` for any other.
+#[derive(Deref, Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct StorePath(PathBuf);
 
 impl TryFrom<PathBuf> for StorePath {
   type Error = Error;
 
   fn try_from(path: PathBuf) -> Result<Self> {
-    if !path.starts_with("/nix/store") {
+    Self::try_from_in(path, Path::new(DEFAULT_STORE_DIR))
+  }
+}
+
+impl StorePath {
+  /// The length of the hash part of a store path base name.
+  const HASH_LENGTH: usize = 32;
+
+  /// Validates that `path` lives inside `store_dir`.
+  pub fn try_from_in(path: PathBuf, store_dir: &Path) -> Result<Self> {
+    if path == store_dir || !path.starts_with(store_dir) {
       bail!(
-        "path {path} must start with /nix/store",
+        "path {path} must start with {store_dir}",
         path = path.display(),
+        store_dir = store_dir.display(),
       );
     }
 
     Ok(Self(path))
   }
-}
 
-impl StorePath {
+  /// Resolves `path`, which may be a symlink into the store such as a profile,
+  /// and validates that the result lives inside the store directory of
+  /// `dirs`.
+  pub fn canonicalize_in(path: &Path, dirs: &StoreDirs) -> Result<Self> {
+    Self::from_physical(&dirs.canonicalize(path)?, dirs)
+  }
+
+  /// Validates that `path`, which is where a store path is found on the
+  /// filesystem, lives inside the store directory of `dirs`.
+  pub fn from_physical(path: &Path, dirs: &StoreDirs) -> Result<Self> {
+    let logical = dirs.logical(path).unwrap_or_else(|| path.to_path_buf());
+
+    Self::try_from_in(logical, &dirs.store)
+  }
+
   /// Parses a Nix store path to extract the packages name and possibly its
   /// version.
   ///
-  /// This function first drops the inputs first 44 chars, since that is exactly
-  /// the length of the `/nix/store/0004yybkm5hnwjyxv129js3mjp7kbrax-` prefix.
-  /// Then it matches that against our store path regex.
+  /// This function first drops the store directory and the hash part, so the
+  /// length of the prefix depends on where the store is located. Then it
+  /// matches the rest against our store path regex.
   fn parse_name_and_version(&self) -> Result<(&str, Option<Version>)> {
     static STORE_PATH_REGEX: sync::LazyLock<regex::Regex> =
       sync::LazyLock::new(|| {
@@ -62,21 +335,25 @@ impl StorePath {
           .expect("failed to compile regex for Nix store paths")
       });
 
-    let path = self.to_str().with_context(|| {
-      format!(
-        "failed to convert path '{path}' to valid unicode",
-        path = self.display(),
-      )
-    })?;
+    let path = self
+      .file_name()
+      .and_then(|name| name.to_str())
+      .with_context(|| {
+        format!(
+          "failed to convert path '{path}' to valid unicode",
+          path = self.display(),
+        )
+      })?;
 
-    // We can strip the path since it _always_ follows the format:
+    // We can strip the base name since it _always_ follows the format:
     //
-    // /nix/store/0004yybkm5hnwjyxv129js3mjp7kbrax-...
-    // ^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^
-    // This part is exactly 44 chars long, so we just remove it.
-    assert_eq!(&path[..11], "/nix/store/");
-    assert_eq!(&path[43..44], "-");
-    let path = &path[44..];
+    // 0004yybkm5hnwjyxv129js3mjp7kbrax-...
+    // ^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^^
+    // This part is exactly 33 chars long, no matter where the store is.
+    if path.as_bytes().get(Self::HASH_LENGTH) != Some(&b'-') {
+      bail!("path '{path}' does not start with a store path hash");
+    }
+    let path = &path[Self::HASH_LENGTH + 1..];
 
     log::debug!("stripped path: {path}");
 
@@ -96,3 +373,135 @@ impl StorePath {
     Ok((name, version))
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use std::{
+    fs,
+    os::unix::fs::symlink,
+    path::{
+      Path,
+      PathBuf,
+    },
+  };
+
+  use super::{
+    StoreDirs,
+    StorePath,
+  };
+
+  #[test]
+  fn store_path_in_custom_store_dir() {
+    let path = PathBuf::from(
+      "/mnt/nix/store/0004yybkm5hnwjyxv129js3mjp7kbrax-hello-2.12.1",
+    );
+
+    StorePath::try_from(path.clone()).unwrap_err();
+
+    let path = StorePath::try_from_in(path, Path::new("/mnt/nix/store"))
+      .expect("path is in the store directory");
+    let (name, version) = path.parse_name_and_version().unwrap();
+
+    assert_eq!(name, "hello");
+    assert_eq!(version.as_deref().map(String::as_str), Some("2.12.1"));
+  }
+
+  /// A store path of a system, like the ones profiles point to.
+  const SYSTEM: &str =
+    "/nix/store/0004yybkm5hnwjyxv129js3mjp7kbrax-nixos-system";
+
+  /// Returns the directories of a store mounted at `root`, with the store
+  /// path [`SYSTEM`] in it.
+  fn mounted_store(root: &Path) -> StoreDirs {
+    fs::create_dir_all(root.join(&SYSTEM[1..]).join("sw")).unwrap();
+    fs::create_dir_all(root.join("nix/var/nix/profiles")).unwrap();
+
+    StoreDirs {
+      root: root.to_path_buf(),
+      ..StoreDirs::default()
+    }
+  }
+
+  #[test]
+  fn mounted_store_paths() {
+    let dirs = StoreDirs {
+      root: PathBuf::from("/mnt"),
+      ..StoreDirs::default()
+    };
+
+    assert_eq!(dirs.database(), Path::new("/mnt/nix/var/nix/db/db.sqlite"));
+    assert_eq!(
+      dirs.physical(Path::new(SYSTEM)),
+      Path::new("/mnt").join(&SYSTEM[1..]),
+    );
+    assert_eq!(
+      dirs.logical(&Path::new("/mnt").join(&SYSTEM[1..])),
+      Some(PathBuf::from(SYSTEM)),
+    );
+    assert_eq!(dirs.logical(Path::new(SYSTEM)), None);
+  }
+
+  #[test]
+  fn canonicalize_in_mounted_store() {
+    let root = tempfile::tempdir().unwrap();
+    let dirs = mounted_store(root.path());
+    let profiles = root.path().join("nix/var/nix/profiles");
+
+    // Profiles point into the store the system was built for, not the one it
+    // is mounted at.
+    symlink(SYSTEM, profiles.join("system-1-link")).unwrap();
+    symlink("system-1-link", profiles.join("system")).unwrap();
+
+    let path = StorePath::canonicalize_in(&profiles.join("system"), &dirs)
+      .expect("profile should resolve inside the root directory");
+    assert_eq!(*path, PathBuf::from(SYSTEM));
+
+    assert_eq!(
+      dirs.canonicalize(&profiles.join("system/sw/..")).unwrap(),
+      root.path().canonicalize().unwrap().join(&SYSTEM[1..]),
+    );
+  }
+
+  #[test]
+  fn canonicalize_keeps_parents_inside_root() {
+    let root = tempfile::tempdir().unwrap();
+    let dirs = mounted_store(root.path());
+    let profiles = root.path().join("nix/var/nix/profiles");
+
+    // More parents than there are directories above the profile, which would
+    // leave the root directory if resolved on the running system.
+    symlink(
+      format!("../../../../../../../..{SYSTEM}"),
+      profiles.join("system"),
+    )
+    .unwrap();
+
+    let path = StorePath::canonicalize_in(&profiles.join("system"), &dirs)
+      .expect("parents should stop at the root directory");
+    assert_eq!(*path, PathBuf::from(SYSTEM));
+  }
+
+  #[test]
+  fn canonicalize_fails_on_symlink_loops() {
+    let root = tempfile::tempdir().unwrap();
+    let dirs = mounted_store(root.path());
+    let profiles = root.path().join("nix/var/nix/profiles");
+
+    symlink("/nix/var/nix/profiles/that", profiles.join("this")).unwrap();
+    symlink("this", profiles.join("that")).unwrap();
+
+    let error = dirs.canonicalize(&profiles.join("this")).unwrap_err();
+    assert!(format!("{error:#}").contains("too many levels of symlinks"));
+  }
+
+  #[test]
+  fn canonicalize_fails_on_dangling_symlinks() {
+    let root = tempfile::tempdir().unwrap();
+    let dirs = mounted_store(root.path());
+    let profiles = root.path().join("nix/var/nix/profiles");
+
+    symlink("../../../store/nonexistent", profiles.join("dangling")).unwrap();
+
+    dirs.canonicalize(&profiles.join("dangling")).unwrap_err();
+  }
+}
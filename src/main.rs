@@ -7,15 +7,43 @@ use std::{
     self,
     Write as _,
   },
-  path::PathBuf,
+  path::{
+    Path,
+    PathBuf,
+  },
   process,
+  sync::Arc,
 };
 
 use anyhow::{
+  Context as _,
   Result,
   anyhow,
+  bail,
 };
 use clap::Parser as _;
+use dix::{
+  BinaryCache,
+  ClosureDiff,
+  ClosureSource,
+  DerivationClosure,
+  DerivationDiff,
+  Diff,
+  Dump,
+  FilesDiff,
+  GcImpact,
+  GcRoot,
+  PackageFilter,
+  RebuildCause,
+  SizeAttribution,
+  SortOrder,
+  StatusFilter,
+  StoreDirs,
+  StorePath,
+  render,
+};
+use regex::Regex;
+use size::Size;
 use yansi::Paint as _;
 
 struct WriteFmt<W: io::Write>(W);
@@ -26,23 +54,520 @@ impl<W: io::Write> fmt::Write for WriteFmt<W> {
   }
 }
 
+/// How to query paths in the local store.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum Backend {
+  /// Read the Nix database directly.
+  #[default]
+  Database,
+  /// Talk to the Nix daemon over its socket.
+  Daemon,
+}
+
+/// How to write the diff.
+#[derive(clap::ValueEnum, Debug, Clone, Copy, PartialEq, Eq, Default)]
+enum Format {
+  /// Colored text for humans.
+  #[default]
+  Text,
+  /// A JSON document for other programs.
+  Json,
+  /// Markdown tables for pull request and changelog comments.
+  Markdown,
+  /// A self-contained HTML report with a sortable table.
+  Html,
+}
+
+/// A closure to diff and where to query it from.
+#[derive(Clone)]
+enum Input {
+  /// A path in the local store, queried from the Nix database.
+  Store(StorePath),
+  /// The root of a dump file that was read into memory.
+  Dump(Arc<Dump>, StorePath),
+  /// A path in a local binary cache.
+  BinaryCache(BinaryCache, StorePath),
+  /// The closure of a derivation that may not be built yet.
+  Derivation(Arc<DerivationClosure>),
+}
+
+impl Input {
+  /// Detects whether `path` refers to the store or to a dump file.
+  ///
+  /// If a binary cache is given, `path` is always a store path inside it,
+  /// since it usually doesn't exist locally. Derivations are resolved to
+  /// their built `out` output, or their only one if they have none. Dumps
+  /// are diffed from their root named `dump_root`, if given.
+  fn new(
+    path: &Path,
+    dirs: &StoreDirs,
+    backend: Backend,
+    binary_cache: Option<&BinaryCache>,
+    dump_root: Option<&str>,
+  ) -> Result<Self> {
+    if let Some(binary_cache) = binary_cache {
+      return Ok(Self::BinaryCache(
+        binary_cache.clone(),
+        StorePath::try_from_in(path.to_path_buf(), &dirs.store)?,
+      ));
+    }
+
+    let canonical = dirs.canonicalize(path)?;
+
+    // Dump files may live inside a store path, like the `registration` file of
+    // `closureInfo`, but are never store paths themselves.
+    if canonical.is_file()
+      && canonical.parent() != Some(&*dirs.physical(&dirs.store))
+    {
+      log::info!(
+        "reading closure of '{path}' from dump",
+        path = path.display(),
+      );
+
+      let dump = Dump::read_file(&canonical, &dirs.store)?;
+      let root = dump.root(dump_root).with_context(|| {
+        format!(
+          "failed to choose the root of dump '{path}', choose one by name \
+           with --dump-root",
+          path = path.display(),
+        )
+      })?;
+
+      return Ok(Self::Dump(Arc::new(dump), root));
+    }
+
+    let path = StorePath::from_physical(&canonical, dirs)?;
+
+    if path.extension().is_none_or(|extension| extension != "drv") {
+      return Ok(Self::Store(path));
+    }
+
+    let mut outputs = Self::Store(path.clone())
+      .open(dirs, backend)?
+      .query_derivation_outputs(&path)?;
+
+    let name = dix::main_output(outputs.keys(), &path)?.to_owned();
+
+    let Some(Some(output)) = outputs.remove(&name) else {
+      bail!(
+        "output '{name}' of derivation '{path}' is not built, build it with \
+         `nix-store --realise {path}` or diff it with --derivations",
+        path = path.display(),
+      );
+    };
+
+    log::info!(
+      "resolved derivation '{path}' to output '{output}'",
+      path = path.display(),
+      output = output.display(),
+    );
+
+    Ok(Self::Store(output))
+  }
+
+  /// Reads the closure of the derivation at `path` from the `.drv` files,
+  /// querying which outputs are built from the local store.
+  fn derivation(
+    path: &Path,
+    dirs: &StoreDirs,
+    backend: Backend,
+  ) -> Result<Self> {
+    let path = StorePath::canonicalize_in(path, dirs)?;
+
+    if path.extension().is_none_or(|extension| extension != "drv") {
+      bail!("path '{path}' is not a derivation", path = path.display());
+    }
+
+    let store = Self::Store(path.clone()).open(dirs, backend)?;
+
+    Ok(Self::Derivation(Arc::new(DerivationClosure::read(
+      &path, dirs, &*store,
+    )?)))
+  }
+
+  /// Reads the closure of the derivation that built `path` from the `.drv`
+  /// files, which has every build input instead of only the runtime
+  /// dependencies.
+  fn deriver(path: &Path, dirs: &StoreDirs, backend: Backend) -> Result<Self> {
+    let path = StorePath::canonicalize_in(path, dirs)?;
+
+    if path.extension().is_some_and(|extension| extension == "drv") {
+      return Self::derivation(&dirs.physical(&path), dirs, backend);
+    }
+
+    let store = Self::Store(path.clone()).open(dirs, backend)?;
+
+    let deriver = store.query_path_info(&path)?.deriver.with_context(|| {
+      format!(
+        "path '{path}' has no deriver, it was added to the store instead of \
+         built",
+        path = path.display(),
+      )
+    })?;
+
+    if !dirs.physical(&deriver).exists() {
+      bail!(
+        "deriver '{deriver}' of path '{path}' is not in the store, it was \
+         garbage collected or the path was substituted",
+        deriver = deriver.display(),
+        path = path.display(),
+      );
+    }
+
+    Ok(Self::Derivation(Arc::new(DerivationClosure::read(
+      &deriver, dirs, &*store,
+    )?)))
+  }
+
+  /// Returns the paths in the closure that are not built yet.
+  fn unbuilt(&self) -> &[StorePath] {
+    match *self {
+      Self::Derivation(ref closure) => closure.unbuilt(),
+      Self::Store(_) | Self::Dump(..) | Self::BinaryCache(..) => &[],
+    }
+  }
+
+  fn path(&self) -> &StorePath {
+    match *self {
+      Self::Store(ref path)
+      | Self::Dump(_, ref path)
+      | Self::BinaryCache(_, ref path) => path,
+      Self::Derivation(ref closure) => closure.root(),
+    }
+  }
+
+  fn open(
+    &self,
+    dirs: &StoreDirs,
+    backend: Backend,
+  ) -> Result<Box<dyn ClosureSource>> {
+    Ok(match *self {
+      Self::Store(_) if backend == Backend::Daemon => {
+        Box::new(dix::connect_daemon(&dirs.daemon_socket(), &dirs.store)?)
+      },
+      Self::Store(_) => Box::new(dix::connect(dirs)?),
+      Self::Dump(ref dump, _) => Box::new(Arc::clone(dump)),
+      Self::BinaryCache(ref binary_cache, _) => Box::new(binary_cache.clone()),
+      Self::Derivation(ref closure) => Box::new(Arc::clone(closure)),
+    })
+  }
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum Command {
+  /// Report how much store space deleting a generation would reclaim.
+  Gc {
+    /// The generation to delete, like /nix/var/nix/profiles/system-68-link.
+    generation: PathBuf,
+
+    /// The GC roots that remain after deleting the generation. Defaults to
+    /// every root in the gcroots directory of the state directory.
+    roots: Vec<PathBuf>,
+  },
+
+  /// Explain why a package changed by diffing its old and new derivations.
+  Explain {
+    /// The old closure, like /run/current-system.
+    old_path: PathBuf,
+
+    /// The new closure, like ./result.
+    new_path: PathBuf,
+
+    /// The name of the package, without the hash and version.
+    #[arg(long)]
+    package: String,
+  },
+
+  /// List the files added, removed and modified between the old and new
+  /// store paths of a package.
+  Files {
+    /// The old closure, like /run/current-system.
+    old_path: PathBuf,
+
+    /// The new closure, like ./result.
+    new_path: PathBuf,
+
+    /// The name of the package, without the hash and version.
+    #[arg(long)]
+    package: String,
+  },
+}
+
 #[derive(clap::Parser, Debug)]
-#[command(version, about)]
+#[expect(clippy::struct_excessive_bools)]
+#[command(
+  version,
+  about,
+  subcommand_negates_reqs = true,
+  override_usage = "dix [OPTIONS] <OLD_PATH> <NEW_PATH>\n       dix [OPTIONS] \
+                    <COMMAND>"
+)]
 struct Cli {
-  old_path: PathBuf,
-  new_path: PathBuf,
+  #[command(subcommand)]
+  command: Option<Command>,
+
+  #[arg(required = true)]
+  old_path: Option<PathBuf>,
+  #[arg(required = true)]
+  new_path: Option<PathBuf>,
+
+  /// The Nix store directory paths must be in.
+  #[arg(
+    long = "store",
+    value_name = "DIR",
+    env = "NIX_STORE_DIR",
+    default_value = dix::DEFAULT_STORE_DIR,
+    global = true
+  )]
+  store_dir: PathBuf,
+
+  /// The Nix state directory containing the database.
+  #[arg(
+    long,
+    value_name = "DIR",
+    env = "NIX_STATE_DIR",
+    default_value = dix::DEFAULT_STATE_DIR,
+    global = true
+  )]
+  state_dir: PathBuf,
+
+  /// The directory the store and state directories are mounted under, like
+  /// /mnt for the target of an installer.
+  #[arg(
+    long,
+    value_name = "DIR",
+    default_value = dix::DEFAULT_ROOT_DIR,
+    global = true
+  )]
+  root: PathBuf,
+
+  /// How to query paths in the local store.
+  #[arg(long, value_enum, default_value_t, global = true)]
+  backend: Backend,
+
+  /// Query the paths from a local binary cache instead of the store.
+  #[arg(long, value_name = "URL", conflicts_with = "backend")]
+  binary_cache: Option<String>,
+
+  /// The name of the root path to diff in dump files with several roots,
+  /// without the hash and version, like nixos-system.
+  #[arg(long, value_name = "NAME", global = true)]
+  dump_root: Option<String>,
+
+  /// Diff the closures of two derivations without building them, reading
+  /// their .drv files and marking the packages that are not built yet.
+  #[arg(long, conflicts_with = "binary_cache")]
+  derivations: bool,
+
+  /// Diff the build-time closures, with every compiler, build tool and
+  /// source used to build the closures, by reading the .drv files of their
+  /// derivers.
+  #[arg(long, conflicts_with_all = ["binary_cache", "derivations"])]
+  build_deps: bool,
+
+  /// The format to write the diff in.
+  #[arg(long, value_enum, default_value_t)]
+  format: Format,
+
+  /// Only show packages with these statuses: changed, upgraded, downgraded,
+  /// added, removed or rebuilt.
+  #[arg(long, value_name = "STATUS", value_delimiter = ',')]
+  only: Vec<StatusFilter>,
+
+  /// Only show packages that are system packages in either closure.
+  #[arg(long)]
+  selected_only: bool,
+
+  /// Only show packages whose name matches any of these regexes.
+  #[arg(long, value_name = "REGEX")]
+  include: Vec<Regex>,
+
+  /// Hide packages whose name matches any of these regexes.
+  #[arg(long, value_name = "REGEX")]
+  exclude: Vec<Regex>,
+
+  /// Only show packages whose size changed by at least this much, like 10MiB.
+  #[arg(long, value_name = "SIZE")]
+  min_size_change: Option<Size>,
+
+  /// Also show packages that were rebuilt without a version change.
+  #[arg(long)]
+  rebuilt: bool,
+
+  /// How to order the packages of each status: name, package-size, version
+  /// or selection.
+  #[arg(long, value_name = "ORDER", default_value = "name")]
+  sort: SortOrder,
+
+  /// Attribute the size difference to the system packages depending on the
+  /// added and removed paths.
+  #[arg(long)]
+  attribute: bool,
+
+  /// Find the changed paths that caused other paths to be rebuilt without a
+  /// version change.
+  #[arg(long)]
+  rebuild_causes: bool,
+
+  /// Quiet mode: diff the closures without writing anything, only exiting
+  /// with 1 if they differ in any package kept by the filters.
+  #[arg(long, conflicts_with_all = ["format", "attribute", "rebuild_causes"])]
+  check: bool,
 
   #[command(flatten)]
   verbose: clap_verbosity_flag::Verbosity,
 }
 
-fn real_main() -> Result<()> {
+/// The exit code when the closures, derivations or files have no differences.
+const EXIT_IDENTICAL: i32 = 0;
+/// The exit code when the closures, derivations or files have differences.
+const EXIT_DIFFERENT: i32 = 1;
+/// The exit code when something failed.
+const EXIT_ERROR: i32 = 2;
+
+/// Reports how much store space deleting `generation` would reclaim, given
+/// the remaining `roots`.
+fn gc(
+  out: &mut impl fmt::Write,
+  dirs: &StoreDirs,
+  backend: Backend,
+  generation: &Path,
+  roots: &[PathBuf],
+) -> Result<()> {
+  let generation = GcRoot::read(generation, dirs)?;
+
+  let roots = if roots.is_empty() {
+    dix::find_gc_roots(dirs)?
+  } else {
+    roots
+      .iter()
+      .map(|root| GcRoot::read(root, dirs))
+      .collect::<Result<Vec<_>>>()?
+  };
+
+  let source = Input::Store(generation.path.clone()).open(dirs, backend)?;
+  let impact = GcImpact::query(&*source, &generation, &roots)?;
+
+  render::write_gc_impact(out, &impact)?;
+
+  Ok(())
+}
+
+/// Diffs the derivations of `package` in the closures of `old_path` and
+/// `new_path`, returning whether they differ.
+fn explain(
+  out: &mut impl fmt::Write,
+  dirs: &StoreDirs,
+  backend: Backend,
+  dump_root: Option<&str>,
+  old_path: &Path,
+  new_path: &Path,
+  package: &str,
+) -> Result<bool> {
+  let old = Input::new(old_path, dirs, backend, None, dump_root)?;
+  let new = Input::new(new_path, dirs, backend, None, dump_root)?;
+
+  let diffs = DerivationDiff::query(
+    &*old.open(dirs, backend)?,
+    old.path(),
+    &*new.open(dirs, backend)?,
+    new.path(),
+    package,
+    dirs,
+  )?;
+
+  for (index, diff) in diffs.iter().enumerate() {
+    if index > 0 {
+      writeln!(out)?;
+    }
+
+    render::write_derivation_diff(out, diff)?;
+  }
+
+  Ok(diffs.iter().any(|diff| !diff.is_empty()))
+}
+
+/// Diffs the files of `package` in the closures of `old_path` and `new_path`,
+/// returning whether they differ.
+fn files(
+  out: &mut impl fmt::Write,
+  dirs: &StoreDirs,
+  backend: Backend,
+  dump_root: Option<&str>,
+  old_path: &Path,
+  new_path: &Path,
+  package: &str,
+) -> Result<bool> {
+  let old = Input::new(old_path, dirs, backend, None, dump_root)?;
+  let new = Input::new(new_path, dirs, backend, None, dump_root)?;
+
+  let diffs = FilesDiff::query(
+    &*old.open(dirs, backend)?,
+    old.path(),
+    &*new.open(dirs, backend)?,
+    new.path(),
+    package,
+    dirs,
+  )?;
+
+  for (index, diff) in diffs.iter().enumerate() {
+    if index > 0 {
+      writeln!(out)?;
+    }
+
+    render::write_files_diff(out, diff)?;
+  }
+
+  Ok(diffs.iter().any(|diff| !diff.changes.is_empty()))
+}
+
+/// Diffs the closures given on the command line, or runs a subcommand.
+///
+/// Returns whether the closures have any package differences, or whether the
+/// derivations or files differ for the subcommands comparing those. Reporting
+/// the impact of garbage collection never counts as a difference.
+fn real_main() -> Result<bool> {
   let Cli {
+    command,
     old_path,
     new_path,
+    store_dir,
+    state_dir,
+    root,
+    backend,
+    binary_cache,
+    dump_root,
+    derivations,
+    build_deps,
+    format,
+    only,
+    selected_only,
+    include,
+    exclude,
+    min_size_change,
+    rebuilt,
+    sort,
+    attribute,
+    rebuild_causes,
+    check,
     verbose,
   } = Cli::parse();
 
+  let filter = PackageFilter {
+    statuses: only,
+    selected_only,
+    include,
+    exclude,
+    min_size_change,
+    rebuilt,
+  };
+
+  let dirs = StoreDirs {
+    store: store_dir,
+    state: state_dir,
+    root,
+  };
+
   yansi::whenever(yansi::Condition::TTY_AND_COLOR);
 
   env_logger::Builder::new()
@@ -62,29 +587,161 @@ fn real_main() -> Result<()> {
 
   let mut out = WriteFmt(io::stdout());
 
-  // Handle to the thread collecting closure size information.
-  let closure_size_handle =
-    dix::spawn_size_diff(old_path.clone(), new_path.clone());
+  match command {
+    Some(Command::Gc { generation, roots }) => {
+      gc(&mut out, &dirs, backend, &generation, &roots)?;
+      return Ok(false);
+    },
+    Some(Command::Explain {
+      old_path,
+      new_path,
+      package,
+    }) => {
+      return explain(
+        &mut out,
+        &dirs,
+        backend,
+        dump_root.as_deref(),
+        &old_path,
+        &new_path,
+        &package,
+      );
+    },
+    Some(Command::Files {
+      old_path,
+      new_path,
+      package,
+    }) => {
+      return files(
+        &mut out,
+        &dirs,
+        backend,
+        dump_root.as_deref(),
+        &old_path,
+        &new_path,
+        &package,
+      );
+    },
+    None => {},
+  }
+
+  let old_path = old_path.expect("clap requires the paths without a command");
+  let new_path = new_path.expect("clap requires the paths without a command");
+
+  let binary_cache = binary_cache
+    .map(|location| BinaryCache::open(&location, &dirs.store))
+    .transpose()?;
 
-  let wrote = dix::write_paths_diffln(&mut out, &old_path, &new_path)?;
+  let (old, new) = if build_deps {
+    (
+      Input::deriver(&old_path, &dirs, backend)?,
+      Input::deriver(&new_path, &dirs, backend)?,
+    )
+  } else if derivations {
+    (
+      Input::derivation(&old_path, &dirs, backend)?,
+      Input::derivation(&new_path, &dirs, backend)?,
+    )
+  } else {
+    (
+      Input::new(
+        &old_path,
+        &dirs,
+        backend,
+        binary_cache.as_ref(),
+        dump_root.as_deref(),
+      )?,
+      Input::new(
+        &new_path,
+        &dirs,
+        backend,
+        binary_cache.as_ref(),
+        dump_root.as_deref(),
+      )?,
+    )
+  };
+
+  let source_old = old.open(&dirs, backend)?;
+  let source_new = new.open(&dirs, backend)?;
+
+  // Handle to the thread collecting closure size information, which isn't
+  // needed when only checking for differences.
+  let closure_size_handle = (!check).then(|| {
+    dix::spawn_size_diff(
+      {
+        let (old, new, dirs) = (old.clone(), new.clone(), dirs.clone());
+        move || Ok((old.open(&dirs, backend)?, new.open(&dirs, backend)?))
+      },
+      old.path().clone(),
+      new.path().clone(),
+    )
+  });
+
+  let mut diff = ClosureDiff::query_packages(
+    &*source_old,
+    old.path(),
+    &*source_new,
+    new.path(),
+  )?;
+  diff.mark_unbuilt(Diff {
+    old: old.unbuilt(),
+    new: new.unbuilt(),
+  });
+  diff.packages.retain(|package| filter.matches(package));
+  sort.sort(&mut diff.packages);
+
+  let Some(closure_size_handle) = closure_size_handle else {
+    return Ok(!diff.packages.is_empty());
+  };
 
   let (size_old, size_new) = closure_size_handle
     .join()
     .map_err(|_| anyhow!("failed to get closure size due to thread error"))??;
 
-  if wrote > 0 {
-    writeln!(out)?;
+  diff.sizes = Diff {
+    old: size_old,
+    new: size_new,
+  };
+
+  if attribute {
+    diff.attribution = Some(SizeAttribution::query(
+      &*source_old,
+      old.path(),
+      &*source_new,
+      new.path(),
+    )?);
   }
 
-  dix::write_size_diffln(&mut out, size_old, size_new)?;
+  if rebuild_causes {
+    diff.rebuild_causes = Some(RebuildCause::query(
+      &*source_old,
+      old.path(),
+      &*source_new,
+      new.path(),
+    )?);
+  }
 
-  Ok(())
+  match format {
+    Format::Text => render::write_text(&mut out, &diff)?,
+    Format::Json => render::write_json(&mut out, &diff)?,
+    Format::Markdown => render::write_markdown(&mut out, &diff)?,
+    Format::Html => render::write_html(&mut out, &diff)?,
+  }
+
+  Ok(!diff.packages.is_empty())
 }
 
 #[allow(clippy::allow_attributes, clippy::exit)]
 fn main() {
-  let Err(error) = real_main() else {
-    return;
+  let error = match real_main() {
+    Ok(differ) => {
+      process::exit(if differ {
+        EXIT_DIFFERENT
+      } else {
+        EXIT_IDENTICAL
+      })
+    },
+    Err(error) => error,
   };
 
   let mut err = io::stderr();
@@ -128,5 +785,5 @@ fn main() {
     };
   }
 
-  process::exit(1);
+  process::exit(EXIT_ERROR);
 }
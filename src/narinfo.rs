@@ -0,0 +1,279 @@
+use std::{
+  fs,
+  io,
+  path::{
+    Path,
+    PathBuf,
+  },
+};
+
+use anyhow::{
+  Context as _,
+  Result,
+  anyhow,
+  bail,
+};
+use size::Size;
+
+use crate::{
+  ClosureSource,
+  PathInfo,
+  StorePath,
+};
+
+/// A local binary cache, such as one created by
+/// `nix copy --to file:///path/to/cache`.
+///
+/// Path infos are read from the `<hash>.narinfo` files in the cache
+/// directory.
+#[derive(Debug, Clone)]
+pub struct BinaryCache {
+  dir:       PathBuf,
+  store_dir: PathBuf,
+}
+
+impl BinaryCache {
+  /// Opens the binary cache at `location`, which is either a directory or a
+  /// `file://` URL.
+  ///
+  /// Fails if the cache was created for a store directory other than
+  /// `store_dir`.
+  pub fn open(location: &str, store_dir: &Path) -> Result<Self> {
+    let dir =
+      PathBuf::from(location.strip_prefix("file://").unwrap_or(location));
+
+    match fs::read_to_string(dir.join("nix-cache-info")) {
+      Ok(cache_info) => {
+        let cache_store_dir = cache_info
+          .lines()
+          .find_map(|line| line.strip_prefix("StoreDir:"))
+          .map(str::trim);
+
+        if let Some(cache_store_dir) = cache_store_dir
+          && Path::new(cache_store_dir) != store_dir
+        {
+          bail!(
+            "binary cache at {dir} is for store {cache_store_dir}, not \
+             {store_dir}",
+            dir = dir.display(),
+            store_dir = store_dir.display(),
+          );
+        }
+      },
+
+      Err(error) if error.kind() == io::ErrorKind::NotFound => {
+        if !dir.is_dir() {
+          bail!(
+            "binary cache at {dir} is not a directory",
+            dir = dir.display(),
+          );
+        }
+
+        log::warn!(
+          "binary cache at {dir} has no nix-cache-info",
+          dir = dir.display(),
+        );
+      },
+
+      Err(error) => {
+        return Err(error).with_context(|| {
+          format!(
+            "failed to read nix-cache-info of binary cache at {dir}",
+            dir = dir.display(),
+          )
+        });
+      },
+    }
+
+    Ok(Self {
+      dir,
+      store_dir: store_dir.to_path_buf(),
+    })
+  }
+
+  fn store_path(&self, base_name: &str) -> Result<StorePath> {
+    StorePath::try_from_in(self.store_dir.join(base_name), &self.store_dir)
+  }
+
+  /// Parses the contents of a narinfo file describing `path`.
+  fn parse_narinfo(&self, path: &StorePath, narinfo: &str) -> Result<PathInfo> {
+    let mut nar_size = None;
+    let mut references = Vec::new();
+    let mut deriver = None;
+
+    for line in narinfo.lines() {
+      let Some((key, value)) = line.split_once(':') else {
+        continue;
+      };
+      let value = value.trim();
+
+      match key {
+        "StorePath" if Path::new(value) != path.as_path() => {
+          bail!("narinfo describes {value} instead");
+        },
+
+        "NarSize" => {
+          nar_size = Some(
+            value
+              .parse::<u64>()
+              .with_context(|| format!("invalid NarSize '{value}'"))?,
+          );
+        },
+
+        "References" => {
+          references = value
+            .split_whitespace()
+            .map(|reference| self.store_path(reference))
+            .collect::<Result<_>>()?;
+        },
+
+        "Deriver" if value != "unknown-deriver" => {
+          deriver = Some(self.store_path(value)?);
+        },
+
+        _ => {},
+      }
+    }
+
+    Ok(PathInfo {
+      path: path.clone(),
+      references,
+      nar_size: Size::from_bytes(
+        nar_size.ok_or_else(|| anyhow!("narinfo is missing NarSize"))?,
+      ),
+      deriver,
+    })
+  }
+}
+
+impl ClosureSource for BinaryCache {
+  fn query_path_info(&self, path: &StorePath) -> Result<PathInfo> {
+    let hash = path
+      .file_name()
+      .and_then(|name| name.to_str())
+      .and_then(|name| name.get(..StorePath::HASH_LENGTH))
+      .with_context(|| {
+        format!(
+          "failed to get hash part of path '{path}'",
+          path = path.display(),
+        )
+      })?;
+
+    let narinfo_path = self.dir.join(format!("{hash}.narinfo"));
+
+    let narinfo = fs::read_to_string(&narinfo_path).with_context(|| {
+      format!(
+        "path '{path}' is not in the binary cache, failed to read {narinfo}",
+        path = path.display(),
+        narinfo = narinfo_path.display(),
+      )
+    })?;
+
+    self.parse_narinfo(path, &narinfo).with_context(|| {
+      format!(
+        "failed to parse {narinfo}",
+        narinfo = narinfo_path.display(),
+      )
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{
+    fs,
+    path::Path,
+  };
+
+  use size::Size;
+  use tempfile::TempDir;
+
+  use super::BinaryCache;
+  use crate::{
+    ClosureSource as _,
+    test_utils::{
+      store_path,
+      store_path_with_hash,
+    },
+  };
+
+  /// Creates a binary cache holding `hello` and its reference `glibc`.
+  fn binary_cache() -> (TempDir, String) {
+    let dir = tempfile::tempdir().unwrap();
+
+    fs::write(
+      dir.path().join("nix-cache-info"),
+      "StoreDir: /nix/store\nWantMassQuery: 1\nPriority: 30\n",
+    )
+    .unwrap();
+    fs::write(
+      dir.path().join("0004yybkm5hnwjyxv129js3mjp7kbrax.narinfo"),
+      "StorePath: /nix/store/0004yybkm5hnwjyxv129js3mjp7kbrax-hello-2.12.1
+URL: nar/0000.nar.xz
+Compression: xz
+NarHash: sha256:0000000000000000000000000000000000000000000000000000
+NarSize: 226560
+References: 0004yybkm5hnwjyxv129js3mjp7kbrax-hello-2.12.1 \
+       1114yybkm5hnwjyxv129js3mjp7kbrax-glibc-2.40-66
+Deriver: 2224yybkm5hnwjyxv129js3mjp7kbrax-hello-2.12.1.drv
+",
+    )
+    .unwrap();
+    fs::write(
+      dir.path().join("1114yybkm5hnwjyxv129js3mjp7kbrax.narinfo"),
+      "StorePath: /nix/store/1114yybkm5hnwjyxv129js3mjp7kbrax-glibc-2.40-66
+NarSize: 30000000
+References:
+",
+    )
+    .unwrap();
+
+    let location = format!("file://{dir}", dir = dir.path().display());
+
+    (dir, location)
+  }
+
+  #[test]
+  fn narinfo_closure_size() {
+    let (_dir, location) = binary_cache();
+    let cache = BinaryCache::open(&location, Path::new("/nix/store")).unwrap();
+
+    assert_eq!(
+      cache
+        .query_closure_size(&store_path("hello-2.12.1"))
+        .unwrap(),
+      Size::from_bytes(30_226_560_u64),
+    );
+  }
+
+  #[test]
+  fn narinfo_deriver() {
+    let (_dir, location) = binary_cache();
+    let cache = BinaryCache::open(&location, Path::new("/nix/store")).unwrap();
+
+    assert_eq!(
+      cache
+        .query_path_info(&store_path("hello-2.12.1"))
+        .unwrap()
+        .deriver,
+      Some(store_path_with_hash('2', "hello-2.12.1.drv")),
+    );
+  }
+
+  #[test]
+  fn narinfo_missing_path() {
+    let (_dir, location) = binary_cache();
+    let cache = BinaryCache::open(&location, Path::new("/nix/store")).unwrap();
+
+    cache
+      .query_path_info(&store_path_with_hash('3', "vim-9.0"))
+      .unwrap_err();
+  }
+
+  #[test]
+  fn binary_cache_of_other_store() {
+    let (_dir, location) = binary_cache();
+
+    BinaryCache::open(&location, Path::new("/mnt/nix/store")).unwrap_err();
+  }
+}
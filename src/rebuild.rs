@@ -0,0 +1,329 @@
+use std::collections::{
+  HashMap,
+  HashSet,
+  VecDeque,
+};
+
+use anyhow::{
+  Context as _,
+  Result,
+};
+
+use crate::{
+  ClosureSource,
+  Diff,
+  PathInfo,
+  StorePath,
+  Version,
+};
+
+/// A path of the new closure whose change explains why other paths were
+/// rebuilt, like an upgraded library that everything links against.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RebuildCause {
+  /// The name of the package, without the hash and version.
+  pub name:     String,
+  /// The versions of the package only in the old closure, and the version of
+  /// the changed path.
+  pub versions: Diff<Vec<Version>>,
+  /// The changed path in the new closure.
+  pub path:     StorePath,
+  /// The amount of paths that were rebuilt at the same version because they
+  /// depend on this path.
+  pub rebuilds: usize,
+}
+
+/// Parses the name and version of `path`, using `<none>` for paths without a
+/// version.
+fn name_and_version(path: &StorePath) -> Option<(&str, Version)> {
+  match path.parse_name_and_version() {
+    Ok((name, version)) => {
+      Some((
+        name,
+        version.unwrap_or_else(|| Version::from("<none>".to_owned())),
+      ))
+    },
+    Err(error) => {
+      log::warn!("error parsing path name and version: {error}");
+      None
+    },
+  }
+}
+
+impl RebuildCause {
+  /// Queries the closures of `path_old` and `path_new` from `source_old` and
+  /// `source_new` respectively and finds the causes of the rebuilds between
+  /// them.
+  #[expect(clippy::missing_errors_doc)]
+  pub fn query(
+    source_old: &dyn ClosureSource,
+    path_old: &StorePath,
+    source_new: &dyn ClosureSource,
+    path_new: &StorePath,
+  ) -> Result<Vec<Self>> {
+    let query = |source: &dyn ClosureSource, path: &StorePath| {
+      source.query_closure_infos(path).with_context(|| {
+        format!(
+          "failed to query dependencies of path '{path}'",
+          path = path.display()
+        )
+      })
+    };
+
+    Ok(Self::from_closures(Diff {
+      old: &query(source_old, path_old)?,
+      new: &query(source_new, path_new)?,
+    }))
+  }
+
+  /// Finds the changed paths of the new closure that explain why other paths
+  /// were rebuilt at the same version, ranked by how many rebuilds they
+  /// cause.
+  ///
+  /// A changed path is a cause if its version changed, or if none of its
+  /// references changed, so something about the path itself must have.
+  /// Every path that was rebuilt because it depends on a cause, directly or
+  /// through other rebuilt paths, is counted for that cause. Causes without
+  /// any rebuilds are left out.
+  #[must_use]
+  pub fn from_closures(infos: Diff<&[PathInfo]>) -> Vec<Self> {
+    let paths_old = infos
+      .old
+      .iter()
+      .map(|info| &info.path)
+      .collect::<HashSet<_>>();
+    let paths_new = infos
+      .new
+      .iter()
+      .map(|info| &info.path)
+      .collect::<HashSet<_>>();
+
+    // The versions of every package that were removed from the old closure.
+    let mut removed = HashMap::<&str, Vec<Version>>::new();
+    for info in infos.old {
+      if paths_new.contains(&info.path) {
+        continue;
+      }
+
+      if let Some((name, version)) = name_and_version(&info.path) {
+        removed.entry(name).or_default().push(version);
+      }
+    }
+
+    let changed = infos
+      .new
+      .iter()
+      .filter(|info| !paths_old.contains(&info.path))
+      .collect::<Vec<_>>();
+    let changed_paths = changed
+      .iter()
+      .map(|info| &info.path)
+      .collect::<HashSet<_>>();
+
+    let mut dependents = HashMap::<&StorePath, Vec<&StorePath>>::new();
+    let mut rebuilt = HashSet::new();
+    let mut causes = Vec::new();
+
+    for &info in &changed {
+      let mut references_changed = false;
+
+      for reference in &info.references {
+        if *reference != info.path && changed_paths.contains(reference) {
+          references_changed = true;
+          dependents.entry(reference).or_default().push(&info.path);
+        }
+      }
+
+      let Some((name, version)) = name_and_version(&info.path) else {
+        continue;
+      };
+
+      let versions_old = removed.get(name).cloned().unwrap_or_default();
+      let is_rebuild = versions_old.contains(&version);
+
+      if is_rebuild {
+        rebuilt.insert(&info.path);
+      }
+
+      if !is_rebuild || !references_changed {
+        causes.push((name, versions_old, version, &info.path));
+      }
+    }
+
+    let mut causes = causes
+      .into_iter()
+      .filter_map(|(name, versions_old, version, path)| {
+        let mut seen = HashSet::from([path]);
+        let mut queue = VecDeque::from([path]);
+
+        while let Some(path) = queue.pop_front() {
+          for &dependent in dependents.get(path).into_iter().flatten() {
+            if seen.insert(dependent) {
+              queue.push_back(dependent);
+            }
+          }
+        }
+
+        let rebuilds = seen
+          .iter()
+          .filter(|&&dependent| {
+            dependent != path && rebuilt.contains(dependent)
+          })
+          .count();
+
+        (rebuilds > 0).then(|| {
+          RebuildCause {
+            name: name.to_owned(),
+            versions: Diff {
+              old: versions_old,
+              new: vec![version],
+            },
+            path: path.clone(),
+            rebuilds,
+          }
+        })
+      })
+      .collect::<Vec<_>>();
+
+    causes.sort_by(|this, that| {
+      that
+        .rebuilds
+        .cmp(&this.rebuilds)
+        .then_with(|| this.name.cmp(&that.name))
+    });
+
+    causes
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use size::Size;
+
+  use super::RebuildCause;
+  use crate::{
+    Diff,
+    PathInfo,
+    StorePath,
+    test_utils::{
+      store_path,
+      store_path_with_hash,
+    },
+  };
+
+  fn info(path: &StorePath, references: &[&StorePath]) -> PathInfo {
+    PathInfo {
+      path:       path.clone(),
+      references: references.iter().copied().cloned().collect(),
+      nar_size:   Size::from_bytes(1_u64),
+      deriver:    None,
+    }
+  }
+
+  /// Returns the name, versions and rebuilds of every cause.
+  fn summarize(causes: &[RebuildCause]) -> Vec<(&str, Vec<&str>, usize)> {
+    causes
+      .iter()
+      .map(|cause| {
+        (
+          cause.name.as_str(),
+          cause
+            .versions
+            .old
+            .iter()
+            .chain(&cause.versions.new)
+            .map(|version| version.as_str())
+            .collect(),
+          cause.rebuilds,
+        )
+      })
+      .collect()
+  }
+
+  #[test]
+  fn version_change_causes_rebuilds() {
+    let openssl_old = store_path("openssl-3.0.13");
+    let openssl_new = store_path("openssl-3.0.14");
+    let curl_old = store_path("curl-8.5");
+    let curl_new = store_path_with_hash('1', "curl-8.5");
+    let git_old = store_path("git-2.43");
+    let git_new = store_path_with_hash('1', "git-2.43");
+
+    let old = [
+      info(&git_old, &[&curl_old]),
+      info(&curl_old, &[&openssl_old]),
+      info(&openssl_old, &[]),
+    ];
+    let new = [
+      info(&git_new, &[&curl_new]),
+      info(&curl_new, &[&openssl_new]),
+      info(&openssl_new, &[]),
+    ];
+
+    let causes = RebuildCause::from_closures(Diff {
+      old: &old,
+      new: &new,
+    });
+
+    assert_eq!(summarize(&causes), [(
+      "openssl",
+      vec!["3.0.13", "3.0.14"],
+      2
+    )]);
+    assert_eq!(causes[0].path, openssl_new);
+  }
+
+  #[test]
+  fn rebuild_without_changed_references_is_a_cause() {
+    let glibc_old = store_path("glibc-2.40");
+    let glibc_new = store_path_with_hash('1', "glibc-2.40");
+    let bash_old = store_path("bash-5.2");
+    let bash_new = store_path_with_hash('1', "bash-5.2");
+    let curl_old = store_path("curl-8.5");
+    let curl_new = store_path_with_hash('1', "curl-8.5");
+    let openssl_old = store_path("openssl-3.0.13");
+    let openssl_new = store_path("openssl-3.0.14");
+
+    let old = [
+      info(&bash_old, &[&glibc_old]),
+      info(&curl_old, &[&openssl_old, &glibc_old]),
+      info(&openssl_old, &[&glibc_old]),
+      info(&glibc_old, &[]),
+    ];
+    let new = [
+      info(&bash_new, &[&glibc_new]),
+      info(&curl_new, &[&openssl_new, &glibc_new]),
+      info(&openssl_new, &[&glibc_new]),
+      info(&glibc_new, &[]),
+    ];
+
+    let causes = RebuildCause::from_closures(Diff {
+      old: &old,
+      new: &new,
+    });
+
+    // Rebuilds reached from several causes count for each of them.
+    assert_eq!(summarize(&causes), [
+      ("glibc", vec!["2.40", "2.40"], 2),
+      ("openssl", vec!["3.0.13", "3.0.14"], 1),
+    ]);
+  }
+
+  #[test]
+  fn causes_without_rebuilds_are_left_out() {
+    let hello_old = store_path("hello-2.12");
+    let hello_new = store_path("hello-2.13");
+    let glibc = store_path("glibc-2.40");
+
+    let old = [info(&hello_old, &[&glibc]), info(&glibc, &[])];
+    let new = [info(&hello_new, &[&glibc]), info(&glibc, &[])];
+
+    assert_eq!(
+      RebuildCause::from_closures(Diff {
+        old: &old,
+        new: &new,
+      }),
+      [],
+    );
+  }
+}
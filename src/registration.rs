@@ -0,0 +1,163 @@
+use std::{
+  iter,
+  path::{
+    Path,
+    PathBuf,
+  },
+};
+
+use anyhow::{
+  Context as _,
+  Result,
+  anyhow,
+};
+use size::Size;
+
+use crate::{
+  Dump,
+  PathInfo,
+  StorePath,
+};
+
+impl Dump {
+  /// Parses a registration file in the format read by `nix-store --load-db`,
+  /// such as the one produced by `closureInfo`.
+  ///
+  /// Every path is described by the following lines:
+  ///
+  /// ```text
+  /// /nix/store/0004yybkm5hnwjyxv129js3mjp7kbrax-hello-2.12.1
+  /// sha256:0000000000000000000000000000000000000000000000000000
+  /// 226560
+  /// /nix/store/2224yybkm5hnwjyxv129js3mjp7kbrax-hello-2.12.1.drv
+  /// 1
+  /// /nix/store/1114yybkm5hnwjyxv129js3mjp7kbrax-glibc-2.40-66
+  /// ```
+  ///
+  /// That is the path, its NAR hash, its NAR size, its deriver (which may be
+  /// empty), the amount of references and the references themselves.
+  pub fn from_registration(
+    registration: &str,
+    store_dir: &Path,
+  ) -> Result<Self> {
+    let mut lines = registration.lines().enumerate();
+    let mut infos = Vec::new();
+
+    let mut next_line = |what: &str| {
+      lines
+        .next()
+        .map(|(index, line)| (index + 1, line))
+        .ok_or_else(|| {
+          anyhow!("unexpected end of registration, expected {what}")
+        })
+    };
+
+    let store_path = |line: usize, path: &str| {
+      StorePath::try_from_in(PathBuf::from(path), store_dir)
+        .with_context(|| format!("invalid store path on line {line}"))
+    };
+
+    loop {
+      let (line, path) = match next_line("path") {
+        Ok((_, "")) | Err(_) => break,
+        Ok(line) => line,
+      };
+      let path = store_path(line, path)?;
+
+      next_line("NAR hash")?;
+
+      let (line, nar_size) = next_line("NAR size")?;
+      let nar_size = nar_size
+        .parse::<u64>()
+        .with_context(|| format!("invalid NAR size on line {line}"))?;
+
+      let deriver = match next_line("deriver")? {
+        (_, "") => None,
+        (line, deriver) => Some(store_path(line, deriver)?),
+      };
+
+      let (line, count) = next_line("reference count")?;
+      let count = count
+        .parse::<usize>()
+        .with_context(|| format!("invalid reference count on line {line}"))?;
+
+      let references = iter::repeat_with(|| {
+        let (line, reference) = next_line("reference")?;
+        store_path(line, reference)
+      })
+      .take(count)
+      .collect::<Result<_>>()?;
+
+      infos.push(PathInfo {
+        path,
+        references,
+        nar_size: Size::from_bytes(nar_size),
+        deriver,
+      });
+    }
+
+    Ok(infos.into_iter().collect())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::path::{
+    Path,
+    PathBuf,
+  };
+
+  use size::Size;
+
+  use crate::{
+    ClosureSource as _,
+    Dump,
+    StorePath,
+  };
+
+  #[test]
+  fn registration() {
+    let dump = Dump::from_registration(
+      "/nix/store/0004yybkm5hnwjyxv129js3mjp7kbrax-hello-2.12.1
+sha256:0000000000000000000000000000000000000000000000000000
+226560
+/nix/store/2224yybkm5hnwjyxv129js3mjp7kbrax-hello-2.12.1.drv
+2
+/nix/store/0004yybkm5hnwjyxv129js3mjp7kbrax-hello-2.12.1
+/nix/store/1114yybkm5hnwjyxv129js3mjp7kbrax-glibc-2.40-66
+/nix/store/1114yybkm5hnwjyxv129js3mjp7kbrax-glibc-2.40-66
+sha256:1111111111111111111111111111111111111111111111111111
+30000000
+
+0
+",
+      Path::new("/nix/store"),
+    )
+    .unwrap();
+
+    let hello = StorePath::try_from(PathBuf::from(
+      "/nix/store/0004yybkm5hnwjyxv129js3mjp7kbrax-hello-2.12.1",
+    ))
+    .unwrap();
+
+    assert_eq!(dump.root(None).unwrap(), hello);
+    assert_eq!(
+      dump.query_closure_size(&hello).unwrap(),
+      Size::from_bytes(30_226_560_u64),
+    );
+  }
+
+  #[test]
+  fn registration_truncated() {
+    Dump::from_registration(
+      "/nix/store/0004yybkm5hnwjyxv129js3mjp7kbrax-hello-2.12.1
+sha256:0000000000000000000000000000000000000000000000000000
+226560
+
+1
+",
+      Path::new("/nix/store"),
+    )
+    .unwrap_err();
+  }
+}
@@ -0,0 +1,245 @@
+use itertools::{
+  EitherOrBoth,
+  Itertools,
+};
+
+use crate::{
+  Diff,
+  Version,
+};
+
+/// How a part of a version is highlighted. The text renderer paints these
+/// red, green and yellow respectively.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Highlight {
+  /// Separators, which are not highlighted.
+  Plain,
+  /// A part only found in the old version.
+  Removed,
+  /// A part only found in the new version.
+  Added,
+  /// A part found in both versions.
+  Unchanged,
+}
+
+/// A run of version text with the same highlight.
+pub(crate) type Segment = (Highlight, String);
+
+fn push(segments: &mut Vec<Segment>, highlight: Highlight, text: &str) {
+  match segments.last_mut() {
+    Some(&mut (last, ref mut last_text)) if last == highlight => {
+      last_text.push_str(text);
+    },
+    _ => segments.push((highlight, text.to_owned())),
+  }
+}
+
+fn push_separator(segments: &mut Vec<Segment>) {
+  if !segments.is_empty() {
+    push(segments, Highlight::Plain, ", ");
+  }
+}
+
+fn push_version(
+  segments: &mut Vec<Segment>,
+  version: &Version,
+  highlight: Highlight,
+) {
+  for component in version {
+    match component {
+      Ok(component) => push(segments, highlight, &component),
+      Err(ignored) => push(segments, Highlight::Plain, ignored),
+    }
+  }
+}
+
+/// Splits the versions of a package into highlighted segments, separating
+/// multiple versions with commas.
+///
+/// Versions present in only one of the closures are highlighted as a whole.
+/// Versions at the same position are compared component by component, and
+/// components by character, unless they look like a hash.
+pub(crate) fn highlight_versions(
+  versions: &Diff<Vec<Version>>,
+) -> Diff<Vec<Segment>> {
+  let mut old = Vec::new();
+  let mut new = Vec::new();
+
+  for diff in Itertools::zip_longest(versions.old.iter(), versions.new.iter()) {
+    match diff {
+      EitherOrBoth::Left(old_version) => {
+        push_separator(&mut old);
+        push_version(&mut old, old_version, Highlight::Removed);
+      },
+
+      EitherOrBoth::Right(new_version) => {
+        push_separator(&mut new);
+        push_version(&mut new, new_version, Highlight::Added);
+      },
+
+      EitherOrBoth::Both(old_version, new_version) => {
+        // Only rebuilt packages have the same versions in both closures,
+        // which are written once.
+        if old_version == new_version {
+          push_separator(&mut old);
+          push_version(&mut old, old_version, Highlight::Unchanged);
+          continue;
+        }
+
+        push_separator(&mut old);
+        push_separator(&mut new);
+
+        let mut old_version: Vec<_> = old_version.into_iter().collect();
+        let mut new_version: Vec<_> = new_version.into_iter().collect();
+
+        let last_same = old_version.last() == new_version.last();
+        let last = (last_same).then(|| {
+          old_version.pop();
+          new_version.pop().unwrap()
+        });
+
+        for diff in Itertools::zip_longest(old_version.into_iter(), new_version)
+        {
+          match diff {
+            EitherOrBoth::Left(old_comp) => {
+              match old_comp {
+                Ok(old_comp) => push(&mut old, Highlight::Removed, &old_comp),
+                Err(ignored) => push(&mut old, Highlight::Plain, ignored),
+              }
+            },
+
+            EitherOrBoth::Right(new_comp) => {
+              match new_comp {
+                Ok(new_comp) => push(&mut new, Highlight::Added, &new_comp),
+                Err(ignored) => push(&mut new, Highlight::Plain, ignored),
+              }
+            },
+
+            EitherOrBoth::Both(Ok(old_comp), Ok(new_comp)) => {
+              let mut difference_started = false;
+              let is_hash = is_hash(&old_comp);
+              let mut buffer = [0_u8; 4];
+
+              for char in diff::chars(*old_comp, *new_comp) {
+                match char {
+                  diff::Result::Both(old_part, new_part) => {
+                    let (old_highlight, new_highlight) = if difference_started {
+                      (Highlight::Removed, Highlight::Added)
+                    } else {
+                      (Highlight::Unchanged, Highlight::Unchanged)
+                    };
+
+                    push(
+                      &mut old,
+                      old_highlight,
+                      old_part.encode_utf8(&mut buffer),
+                    );
+                    push(
+                      &mut new,
+                      new_highlight,
+                      new_part.encode_utf8(&mut buffer),
+                    );
+                  },
+                  diff::Result::Left(old_part) => {
+                    difference_started = is_hash;
+                    push(
+                      &mut old,
+                      Highlight::Removed,
+                      old_part.encode_utf8(&mut buffer),
+                    );
+                  },
+                  diff::Result::Right(new_part) => {
+                    difference_started = is_hash;
+                    push(
+                      &mut new,
+                      Highlight::Added,
+                      new_part.encode_utf8(&mut buffer),
+                    );
+                  },
+                }
+              }
+            },
+
+            EitherOrBoth::Both(old_comp, new_comp) => {
+              match old_comp {
+                Ok(old_comp) => push(&mut old, Highlight::Removed, &old_comp),
+                Err(old_comp) => push(&mut old, Highlight::Plain, old_comp),
+              }
+
+              match new_comp {
+                Ok(new_comp) => push(&mut new, Highlight::Added, &new_comp),
+                Err(new_comp) => push(&mut new, Highlight::Plain, new_comp),
+              }
+            },
+          }
+        }
+
+        if last_same && let Some(Ok(last)) = last {
+          push(&mut old, Highlight::Unchanged, &last);
+          push(&mut new, Highlight::Unchanged, &last);
+        }
+      },
+    }
+  }
+
+  Diff { old, new }
+}
+
+#[expect(clippy::cast_precision_loss)]
+fn dissimilar_score(input: &str) -> f64 {
+  input
+    .chars()
+    .chunk_by(char::is_ascii_digit)
+    .into_iter()
+    .map(|(_, chunk)| (2.1_f64).powf(chunk.count() as f64))
+    .sum::<f64>()
+    / input.chars().count() as f64
+}
+
+fn is_hash(input: &str) -> bool {
+  dissimilar_score(input) < 70.0
+}
+
+#[cfg(test)]
+mod tests {
+  use super::{
+    Highlight,
+    highlight_versions,
+  };
+  use crate::{
+    Diff,
+    Version,
+  };
+
+  #[test]
+  fn highlight() {
+    let versions = Diff {
+      old: vec![Version::from("1.2.3".to_owned())],
+      new: vec![
+        Version::from("1.2.4".to_owned()),
+        Version::from("2.0".to_owned()),
+      ],
+    };
+
+    let segments = highlight_versions(&versions);
+
+    assert_eq!(segments.old, [
+      (Highlight::Unchanged, "1".to_owned()),
+      (Highlight::Plain, ".".to_owned()),
+      (Highlight::Unchanged, "2".to_owned()),
+      (Highlight::Plain, ".".to_owned()),
+      (Highlight::Removed, "3".to_owned()),
+    ]);
+    assert_eq!(segments.new, [
+      (Highlight::Unchanged, "1".to_owned()),
+      (Highlight::Plain, ".".to_owned()),
+      (Highlight::Unchanged, "2".to_owned()),
+      (Highlight::Plain, ".".to_owned()),
+      (Highlight::Added, "4".to_owned()),
+      (Highlight::Plain, ", ".to_owned()),
+      (Highlight::Added, "2".to_owned()),
+      (Highlight::Plain, ".".to_owned()),
+      (Highlight::Added, "0".to_owned()),
+    ]);
+  }
+}
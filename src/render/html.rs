@@ -0,0 +1,552 @@
+use std::fmt;
+
+use super::{
+  attribution_rows,
+  format_cause_versions,
+  format_counts,
+  format_size_change,
+  format_size_diff,
+  highlight::{
+    Highlight,
+    Segment,
+    highlight_versions,
+  },
+};
+use crate::{
+  Change,
+  ClosureDiff,
+  DerivationSelectionStatus,
+  DiffStatus,
+  PackageDiff,
+  StorePath,
+};
+
+/// The style sheet of the report, using the colors of the text renderer.
+const STYLE: &str = "
+:root {
+  color-scheme: light dark;
+  --red: #c01c28;
+  --green: #26a269;
+  --yellow: #a87b00;
+  --cyan: #0e8ca0;
+  --magenta: #a347ba;
+  --blue: #1c71d8;
+}
+@media (prefers-color-scheme: dark) {
+  :root {
+    --red: #f66151;
+    --green: #33d17a;
+    --yellow: #f6d32d;
+    --cyan: #5bc8dc;
+    --magenta: #dc8add;
+    --blue: #62a0ea;
+  }
+}
+body { font-family: sans-serif; margin: 2em; }
+code, td { font-family: monospace; }
+table { border-collapse: collapse; margin-top: 1em; }
+th { cursor: pointer; user-select: none; text-align: left; }
+th, td { padding: 0.2em 1em 0.2em 0; }
+tr.selected .name { font-weight: bold; }
+.status { font-weight: bold; }
+.upgrade-downgrade { color: var(--yellow); }
+.upgraded { color: var(--cyan); }
+.downgraded { color: var(--magenta); }
+.added { color: var(--green); }
+.removed { color: var(--red); }
+.rebuilt { color: var(--blue); }
+.unchanged { color: var(--yellow); }
+.unbuilt { color: var(--yellow); }
+";
+
+/// The script making the table sortable and filterable.
+const SCRIPT: &str = r#"
+const table = document.getElementById("packages");
+const body = table.tBodies[0];
+const filter = document.getElementById("filter");
+const status = document.getElementById("status");
+
+function update() {
+  const query = filter.value.toLowerCase();
+
+  for (const row of body.rows) {
+    const name = row.cells[1].textContent.toLowerCase();
+    const category = row.dataset.category;
+
+    row.hidden = !name.includes(query)
+      || (status.value !== "" && category !== status.value);
+  }
+}
+
+filter.addEventListener("input", update);
+status.addEventListener("change", update);
+
+for (const [index, header] of Array.from(table.tHead.rows[0].cells).entries()) {
+  header.addEventListener("click", () => {
+    const ascending = header.dataset.order !== "ascending";
+    header.dataset.order = ascending ? "ascending" : "descending";
+
+    const rows = Array.from(body.rows).sort((this_, that) => {
+      const thisCell = this_.cells[index];
+      const thatCell = that.cells[index];
+
+      // Numeric columns like the size change can be negative, which a string
+      // comparison would order by magnitude.
+      if (thisCell.dataset.sort !== undefined && thatCell.dataset.sort !== undefined) {
+        return Number(thisCell.dataset.sort) - Number(thatCell.dataset.sort);
+      }
+
+      return thisCell.textContent.localeCompare(thatCell.textContent, undefined, { numeric: true });
+    });
+
+    if (!ascending) {
+      rows.reverse();
+    }
+
+    body.append(...rows);
+  });
+}
+"#;
+
+/// Escapes the characters of `text` that have a meaning in HTML.
+fn escape(text: &str) -> String {
+  let mut escaped = String::with_capacity(text.len());
+
+  for char in text.chars() {
+    match char {
+      '&' => escaped.push_str("&amp;"),
+      '<' => escaped.push_str("&lt;"),
+      '>' => escaped.push_str("&gt;"),
+      '"' => escaped.push_str("&quot;"),
+      '\'' => escaped.push_str("&#39;"),
+      _ => escaped.push(char),
+    }
+  }
+
+  escaped
+}
+
+fn status_class(status: DiffStatus) -> &'static str {
+  match status {
+    DiffStatus::Changed(Change::UpgradeDowngrade) => "upgrade-downgrade",
+    DiffStatus::Changed(Change::Upgraded) => "upgraded",
+    DiffStatus::Changed(Change::Downgraded) => "downgraded",
+    DiffStatus::Added => "added",
+    DiffStatus::Removed => "removed",
+    DiffStatus::Rebuilt => "rebuilt",
+  }
+}
+
+fn write_segments(
+  writer: &mut impl fmt::Write,
+  segments: &[Segment],
+) -> fmt::Result {
+  for &(highlight, ref text) in segments {
+    let class = match highlight {
+      Highlight::Plain => {
+        write!(writer, "{text}", text = escape(text))?;
+        continue;
+      },
+      Highlight::Removed => "removed",
+      Highlight::Added => "added",
+      Highlight::Unchanged => "unchanged",
+    };
+
+    write!(
+      writer,
+      r#"<span class="{class}">{text}</span>"#,
+      text = escape(text),
+    )?;
+  }
+
+  Ok(())
+}
+
+fn write_package(
+  writer: &mut impl fmt::Write,
+  package: &PackageDiff,
+) -> fmt::Result {
+  let (category, order) = match package.status {
+    DiffStatus::Changed(_) => ("changed", 0_u8),
+    DiffStatus::Added => ("added", 1),
+    DiffStatus::Removed => ("removed", 2),
+    DiffStatus::Rebuilt => ("rebuilt", 3),
+  };
+
+  let selected = match package.selection {
+    DerivationSelectionStatus::Selected
+    | DerivationSelectionStatus::NewlySelected => r#" class="selected""#,
+    DerivationSelectionStatus::Unselected
+    | DerivationSelectionStatus::NewlyUnselected => "",
+  };
+
+  writeln!(writer, r#"<tr data-category="{category}"{selected}>"#)?;
+  writeln!(
+    writer,
+    r#"<td data-sort="{order}">[<span class="status {class}">{status}</span>{selection}]</td>"#,
+    class = status_class(package.status),
+    status = package.status.char().value,
+    selection = package.selection.char().value,
+  )?;
+  writeln!(
+    writer,
+    r#"<td class="name">{name}</td>"#,
+    name = escape(&package.name),
+  )?;
+
+  let versions = highlight_versions(&package.versions);
+
+  write!(writer, "<td>")?;
+  write_segments(writer, &versions.old)?;
+  writeln!(writer, "</td>")?;
+  write!(writer, "<td>")?;
+  write_segments(writer, &versions.new)?;
+  writeln!(writer, "</td>")?;
+
+  if package.unbuilt {
+    write!(
+      writer,
+      r#"<td data-sort="0"><span class="unbuilt">(not built)</span>"#,
+    )?;
+  } else {
+    let size_diff = package.sizes.new - package.sizes.old;
+    let size_change = format!(
+      r#"<span class="{class}">{size_change}</span>"#,
+      class = if size_diff.bytes() > 0 {
+        "added"
+      } else {
+        "removed"
+      },
+      size_change = format_size_change(size_diff),
+    );
+
+    match package.status {
+      DiffStatus::Changed(_) | DiffStatus::Rebuilt => {
+        write!(
+          writer,
+          r#"<td data-sort="{bytes}">{size_old} -&gt; {size_new} ({size_change})"#,
+          bytes = size_diff.bytes(),
+          size_old = package.sizes.old,
+          size_new = package.sizes.new,
+        )?;
+      },
+      DiffStatus::Added | DiffStatus::Removed => {
+        write!(
+          writer,
+          r#"<td data-sort="{bytes}">{size_change}"#,
+          bytes = size_diff.bytes(),
+        )?;
+      },
+    }
+  }
+  writeln!(writer, "</td>")?;
+
+  writeln!(writer, "</tr>")
+}
+
+fn file_name(path: &StorePath) -> String {
+  path
+    .file_name()
+    .map_or_else(String::new, |name| name.to_string_lossy().into_owned())
+}
+
+/// Writes the diff as a single HTML document that does not load any other
+/// resources, so it can be archived on its own.
+///
+/// The packages are written to a table that can be sorted by clicking its
+/// headers and filtered by name and status, with the versions highlighted
+/// like the text renderer does. If queried, the attribution of the size
+/// difference and the causes of rebuilds follow in tables of their own.
+///
+/// # Errors
+///
+/// Returns `Err` when writing to `writer` fails.
+pub fn write_html(
+  writer: &mut impl fmt::Write,
+  diff: &ClosureDiff,
+) -> fmt::Result {
+  writeln!(writer, "<!DOCTYPE html>")?;
+  writeln!(writer, r#"<html lang="en">"#)?;
+  writeln!(writer, "<head>")?;
+  writeln!(writer, r#"<meta charset="utf-8">"#)?;
+  writeln!(
+    writer,
+    r#"<meta name="viewport" content="width=device-width, initial-scale=1">"#,
+  )?;
+  writeln!(
+    writer,
+    "<title>dix: {old} -&gt; {new}</title>",
+    old = escape(&file_name(&diff.paths.old)),
+    new = escape(&file_name(&diff.paths.new)),
+  )?;
+  writeln!(writer, "<style>{STYLE}</style>")?;
+  writeln!(writer, "</head>")?;
+  writeln!(writer, "<body>")?;
+
+  writeln!(
+    writer,
+    "<p><b>&lt;&lt;&lt;</b> <code>{old}</code><br>",
+    old = escape(&diff.paths.old.display().to_string()),
+  )?;
+  writeln!(
+    writer,
+    "<b>&gt;&gt;&gt;</b> <code>{new}</code></p>",
+    new = escape(&diff.paths.new.display().to_string()),
+  )?;
+
+  let summary = diff.summary();
+
+  writeln!(
+    writer,
+    "<p><b>PATHS:</b> {paths_old} -&gt; {paths_new}<br>",
+    paths_old = summary.paths.old,
+    paths_new = summary.paths.new,
+  )?;
+  writeln!(
+    writer,
+    "<b>PACKAGES:</b> {packages}<br>",
+    packages = format_counts(&summary, false),
+  )?;
+  writeln!(
+    writer,
+    "<b>SELECTED:</b> {selected}<br>",
+    selected = format_counts(&summary, true),
+  )?;
+
+  if diff.unbuilt {
+    writeln!(
+      writer,
+      r#"<b>SIZE:</b> <span class="unbuilt">unknown, some paths are not built</span></p>"#,
+    )?;
+  } else {
+    let size_diff = diff.sizes.new - diff.sizes.old;
+
+    writeln!(
+      writer,
+      r#"<b>SIZE:</b> <span class="removed">{size_old}</span> -&gt; <span class="added">{size_new}</span><br>"#,
+      size_old = diff.sizes.old,
+      size_new = diff.sizes.new,
+    )?;
+    writeln!(
+      writer,
+      r#"<b>DIFF:</b> <span class="{class}">{size_diff}</span><br>"#,
+      class = if size_diff.bytes() > 0 {
+        "added"
+      } else {
+        "removed"
+      },
+    )?;
+    writeln!(
+      writer,
+      r#"<b>TRANSFER:</b> <span class="added">{size_new}</span> to copy, <span class="removed">{size_old}</span> no longer needed</p>"#,
+      size_old = diff.unique_sizes.old,
+      size_new = diff.unique_sizes.new,
+    )?;
+  }
+
+  writeln!(
+    writer,
+    r#"<input id="filter" type="search" placeholder="Filter packages">"#,
+  )?;
+  writeln!(writer, r#"<select id="status">"#)?;
+  writeln!(writer, r#"<option value="">All</option>"#)?;
+  writeln!(writer, r#"<option value="changed">Changed</option>"#)?;
+  writeln!(writer, r#"<option value="added">Added</option>"#)?;
+  writeln!(writer, r#"<option value="removed">Removed</option>"#)?;
+  writeln!(writer, r#"<option value="rebuilt">Rebuilt</option>"#)?;
+  writeln!(writer, "</select>")?;
+
+  writeln!(writer, r#"<table id="packages">"#)?;
+  writeln!(
+    writer,
+    "<thead><tr><th>Status</th><th>Package</th><th>Old</th><th>New</\
+     th><th>Size</th></tr></thead>",
+  )?;
+  writeln!(writer, "<tbody>")?;
+
+  for package in &diff.packages {
+    write_package(writer, package)?;
+  }
+
+  writeln!(writer, "</tbody>")?;
+  writeln!(writer, "</table>")?;
+
+  if let Some(ref attribution) = diff.attribution {
+    writeln!(writer, r#"<table id="attribution">"#)?;
+    writeln!(
+      writer,
+      "<thead><tr><th>Package</th><th>Exclusive</th><th>Shared</th></tr></\
+       thead>",
+    )?;
+    writeln!(writer, "<tbody>")?;
+
+    for (name, exclusive, shared) in attribution_rows(attribution) {
+      writeln!(
+        writer,
+        "<tr><td>{name}</td><td>{exclusive}</td><td>{shared}</td></tr>",
+        name = escape(name),
+        exclusive = format_size_diff(exclusive),
+        shared = format_size_diff(shared),
+      )?;
+    }
+
+    writeln!(writer, "</tbody>")?;
+    writeln!(writer, "</table>")?;
+  }
+
+  if let Some(ref causes) = diff.rebuild_causes
+    && !causes.is_empty()
+  {
+    writeln!(writer, r#"<table id="rebuild-causes">"#)?;
+    writeln!(
+      writer,
+      "<thead><tr><th>Package</th><th>Versions</th><th>Rebuilds</th></tr></\
+       thead>",
+    )?;
+    writeln!(writer, "<tbody>")?;
+
+    for cause in causes {
+      writeln!(
+        writer,
+        "<tr><td>{name}</td><td>{versions}</td><td>{rebuilds}</td></tr>",
+        name = escape(&cause.name),
+        versions = escape(&format_cause_versions(cause)),
+        rebuilds = cause.rebuilds,
+      )?;
+    }
+
+    writeln!(writer, "</tbody>")?;
+    writeln!(writer, "</table>")?;
+  }
+
+  writeln!(writer, "<script>{SCRIPT}</script>")?;
+  writeln!(writer, "</body>")?;
+  writeln!(writer, "</html>")
+}
+
+#[cfg(test)]
+mod tests {
+  use size::Size;
+
+  use super::{
+    escape,
+    write_html,
+  };
+  use crate::{
+    Change,
+    ClosureDiff,
+    DerivationSelectionStatus,
+    Diff,
+    DiffStatus,
+    PackageDiff,
+    Version,
+    test_utils::store_path,
+  };
+
+  fn closure_diff(packages: Vec<PackageDiff>) -> ClosureDiff {
+    ClosureDiff {
+      paths: Diff {
+        old: store_path("old-system"),
+        new: store_path("new-system"),
+      },
+      sizes: Diff {
+        old: Size::from_bytes(1024_u64),
+        new: Size::from_bytes(512_u64),
+      },
+      path_counts: Diff { old: 2, new: 1 },
+      unique_sizes: Diff {
+        old: Size::from_bytes(1024_u64),
+        new: Size::from_bytes(512_u64),
+      },
+      unbuilt: false,
+      packages,
+      attribution: None,
+      rebuild_causes: None,
+    }
+  }
+
+  fn write(diff: &ClosureDiff) -> String {
+    let mut out = String::new();
+    write_html(&mut out, diff).unwrap();
+    out
+  }
+
+  #[test]
+  fn html_is_self_contained() {
+    let out = write(&closure_diff(Vec::new()));
+
+    assert!(out.starts_with("<!DOCTYPE html>\n"));
+    assert!(out.contains(
+      "<title>dix: 0004yybkm5hnwjyxv129js3mjp7kbrax-old-system -&gt; \
+       0004yybkm5hnwjyxv129js3mjp7kbrax-new-system</title>"
+    ));
+    assert!(out.ends_with("</html>\n"));
+    assert!(!out.contains("http"));
+  }
+
+  #[test]
+  fn html_removed_package() {
+    let out = write(&closure_diff(vec![PackageDiff {
+      name:      "vim".to_owned(),
+      versions:  Diff {
+        old: vec![Version::from("<none>".to_owned())],
+        new: Vec::new(),
+      },
+      sizes:     Diff {
+        old: Size::from_bytes(10_u64),
+        new: Size::from_bytes(0_u64),
+      },
+      status:    DiffStatus::Removed,
+      selection: DerivationSelectionStatus::NewlyUnselected,
+      unbuilt:   false,
+    }]));
+
+    assert!(out.contains(r#"<tr data-category="removed">"#));
+    assert!(out.contains(
+      r#"<td data-sort="2">[<span class="status removed">R</span>-]</td>"#
+    ));
+    assert!(out.contains(r#"<span class="removed">&lt;none&gt;</span>"#));
+    assert!(out.contains(r#"<td data-sort="-10">"#));
+  }
+
+  #[test]
+  fn html_selected_package() {
+    let out = write(&closure_diff(vec![PackageDiff {
+      name:      "firefox".to_owned(),
+      versions:  Diff {
+        old: vec![Version::from("120.0".to_owned())],
+        new: vec![Version::from("121.0".to_owned())],
+      },
+      sizes:     Diff::default(),
+      status:    DiffStatus::Changed(Change::Upgraded),
+      selection: DerivationSelectionStatus::Selected,
+      unbuilt:   true,
+    }]));
+
+    assert!(out.contains(r#"<tr data-category="changed" class="selected">"#));
+    assert!(out.contains(
+      r#"<td data-sort="0">[<span class="status upgraded">U</span>*]</td>"#
+    ));
+    assert!(
+      out.contains(r#"<td data-sort="0"><span class="unbuilt">(not built)"#)
+    );
+  }
+
+  #[test]
+  fn html_sizes() {
+    let out = write(&closure_diff(Vec::new()));
+
+    assert!(out.contains(r#"<b>DIFF:</b> <span class="removed">"#));
+    assert!(out.contains(
+      r#"<b>TRANSFER:</b> <span class="added">512 bytes</span> to copy"#
+    ));
+  }
+
+  #[test]
+  fn html_escape() {
+    assert_eq!(
+      escape(r#"<a href="x">Tom & Jerry's</a>"#),
+      "&lt;a href=&quot;x&quot;&gt;Tom &amp; Jerry&#39;s&lt;/a&gt;",
+    );
+  }
+}
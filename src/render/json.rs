@@ -0,0 +1,407 @@
+use std::{
+  fmt,
+  ops,
+  path::Path,
+};
+
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::{
+  ClosureDiff,
+  DerivationSelectionStatus,
+  DiffStatus,
+  Summary,
+  Version,
+  diff::split_version_count,
+};
+
+/// The version of the schema written by [`write_json`]. It is increased
+/// whenever a change is made that existing consumers may not understand.
+///
+/// Version 2 added the `rebuilt` status and summary, the `unbuilt`,
+/// `attribution` and `rebuild_causes` fields, and writes versions as objects
+/// with a count instead of strings like `"2.12 ×2"` and `"<none>"`.
+pub const JSON_SCHEMA_VERSION: u32 = 2;
+
+#[derive(Serialize)]
+struct JsonDiff<'a> {
+  version:        u32,
+  old:            JsonClosure<'a>,
+  new:            JsonClosure<'a>,
+  summary:        Summary,
+  packages:       Vec<JsonPackage<'a>>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  attribution:    Option<JsonAttribution<'a>>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  rebuild_causes: Option<Vec<JsonRebuildCause<'a>>>,
+}
+
+#[derive(Serialize)]
+struct JsonClosure<'a> {
+  path:        &'a Path,
+  size:        Option<i64>,
+  unique_size: Option<i64>,
+}
+
+#[derive(Serialize)]
+struct JsonPackage<'a> {
+  name:         &'a str,
+  status:       DiffStatus,
+  selection:    DerivationSelectionStatus,
+  old_versions: Vec<JsonVersion<'a>>,
+  new_versions: Vec<JsonVersion<'a>>,
+  old_size:     Option<i64>,
+  new_size:     Option<i64>,
+  #[serde(skip_serializing_if = "ops::Not::not")]
+  unbuilt:      bool,
+}
+
+#[derive(Serialize)]
+struct JsonVersion<'a> {
+  version: Option<&'a str>,
+  count:   usize,
+}
+
+#[derive(Serialize)]
+struct JsonAttribution<'a> {
+  packages:              Vec<JsonPackageAttribution<'a>>,
+  old_shared_size:       i64,
+  new_shared_size:       i64,
+  old_unattributed_size: i64,
+  new_unattributed_size: i64,
+}
+
+#[derive(Serialize)]
+struct JsonPackageAttribution<'a> {
+  name:               &'a str,
+  old_exclusive_size: i64,
+  new_exclusive_size: i64,
+  old_shared_size:    i64,
+  new_shared_size:    i64,
+}
+
+#[derive(Serialize)]
+struct JsonRebuildCause<'a> {
+  name:         &'a str,
+  old_versions: Vec<JsonVersion<'a>>,
+  new_versions: Vec<JsonVersion<'a>>,
+  path:         &'a Path,
+  rebuilds:     usize,
+}
+
+fn json_versions(versions: &[Version]) -> Vec<JsonVersion<'_>> {
+  versions
+    .iter()
+    .map(|version| {
+      let (version, count) = split_version_count(version);
+
+      JsonVersion { version, count }
+    })
+    .collect()
+}
+
+/// Writes the package diff and closure sizes as a JSON document.
+///
+/// The document has the following schema, see the README for details:
+///
+/// ```json
+/// {
+///   "version": 2,
+///   "old": { "path": "/nix/store/...", "size": 123, "unique_size": 12 },
+///   "new": { "path": "/nix/store/...", "size": 456, "unique_size": 345 },
+///   "summary": {
+///     "upgraded": { "packages": 1, "selected": 1 },
+///     "downgraded": { "packages": 0, "selected": 0 },
+///     "upgrade_downgrade": { "packages": 0, "selected": 0 },
+///     "added": { "packages": 0, "selected": 0 },
+///     "removed": { "packages": 0, "selected": 0 },
+///     "rebuilt": { "packages": 0, "selected": 0 },
+///     "paths": { "old": 1000, "new": 1001 }
+///   },
+///   "packages": [
+///     {
+///       "name": "firefox",
+///       "status": "upgraded",
+///       "selection": "selected",
+///       "old_versions": [{ "version": "120.0", "count": 1 }],
+///       "new_versions": [{ "version": "121.0", "count": 1 }],
+///       "old_size": 200000000,
+///       "new_size": 210000000
+///     }
+///   ],
+///   "attribution": {
+///     "packages": [
+///       {
+///         "name": "firefox",
+///         "old_exclusive_size": 200000000,
+///         "new_exclusive_size": 210000000,
+///         "old_shared_size": 0,
+///         "new_shared_size": 0
+///       }
+///     ],
+///     "old_shared_size": 1000,
+///     "new_shared_size": 2000,
+///     "old_unattributed_size": 3000,
+///     "new_unattributed_size": 4000
+///   },
+///   "rebuild_causes": [
+///     {
+///       "name": "openssl",
+///       "old_versions": [{ "version": "3.0.13", "count": 1 }],
+///       "new_versions": [{ "version": "3.0.14", "count": 1 }],
+///       "path": "/nix/store/...-openssl-3.0.14",
+///       "rebuilds": 812
+///     }
+///   ]
+/// }
+/// ```
+///
+/// The `attribution` and `rebuild_causes` are only written if they were
+/// queried. Sizes are `null` if some of the paths they cover are not built.
+#[expect(clippy::missing_errors_doc)]
+pub fn write_json(
+  writer: &mut impl fmt::Write,
+  diff: &ClosureDiff,
+) -> Result<()> {
+  let json = JsonDiff {
+    version:        JSON_SCHEMA_VERSION,
+    old:            JsonClosure {
+      path:        &diff.paths.old,
+      size:        (!diff.unbuilt).then(|| diff.sizes.old.bytes()),
+      unique_size: (!diff.unbuilt).then(|| diff.unique_sizes.old.bytes()),
+    },
+    new:            JsonClosure {
+      path:        &diff.paths.new,
+      size:        (!diff.unbuilt).then(|| diff.sizes.new.bytes()),
+      unique_size: (!diff.unbuilt).then(|| diff.unique_sizes.new.bytes()),
+    },
+    summary:        diff.summary(),
+    packages:       diff
+      .packages
+      .iter()
+      .map(|package| {
+        JsonPackage {
+          name:         &package.name,
+          status:       package.status,
+          selection:    package.selection,
+          old_versions: json_versions(&package.versions.old),
+          new_versions: json_versions(&package.versions.new),
+          old_size:     (!package.unbuilt).then(|| package.sizes.old.bytes()),
+          new_size:     (!package.unbuilt).then(|| package.sizes.new.bytes()),
+          unbuilt:      package.unbuilt,
+        }
+      })
+      .collect(),
+    attribution:    diff.attribution.as_ref().map(|attribution| {
+      JsonAttribution {
+        packages:              attribution
+          .packages
+          .iter()
+          .map(|package| {
+            JsonPackageAttribution {
+              name:               &package.name,
+              old_exclusive_size: package.exclusive.old.bytes(),
+              new_exclusive_size: package.exclusive.new.bytes(),
+              old_shared_size:    package.shared.old.bytes(),
+              new_shared_size:    package.shared.new.bytes(),
+            }
+          })
+          .collect(),
+        old_shared_size:       attribution.shared.old.bytes(),
+        new_shared_size:       attribution.shared.new.bytes(),
+        old_unattributed_size: attribution.unattributed.old.bytes(),
+        new_unattributed_size: attribution.unattributed.new.bytes(),
+      }
+    }),
+    rebuild_causes: diff.rebuild_causes.as_ref().map(|causes| {
+      causes
+        .iter()
+        .map(|cause| {
+          JsonRebuildCause {
+            name:         &cause.name,
+            old_versions: json_versions(&cause.versions.old),
+            new_versions: json_versions(&cause.versions.new),
+            path:         &cause.path,
+            rebuilds:     cause.rebuilds,
+          }
+        })
+        .collect()
+    }),
+  };
+
+  writeln!(
+    writer,
+    "{json}",
+    json = serde_json::to_string_pretty(&json)?
+  )?;
+
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use serde_json::json;
+  use size::Size;
+
+  use super::write_json;
+  use crate::{
+    Change,
+    ClosureDiff,
+    DerivationSelectionStatus,
+    Diff,
+    DiffStatus,
+    PackageDiff,
+    Version,
+    test_utils::store_path,
+  };
+
+  fn closure_diff(packages: Vec<PackageDiff>) -> ClosureDiff {
+    ClosureDiff {
+      paths: Diff {
+        old: store_path("old-system"),
+        new: store_path("new-system"),
+      },
+      sizes: Diff {
+        old: Size::from_bytes(1024_u64),
+        new: Size::from_bytes(2048_u64),
+      },
+      path_counts: Diff { old: 10, new: 11 },
+      unique_sizes: Diff {
+        old: Size::from_bytes(512_u64),
+        new: Size::from_bytes(1536_u64),
+      },
+      unbuilt: false,
+      packages,
+      attribution: None,
+      rebuild_causes: None,
+    }
+  }
+
+  fn write(diff: &ClosureDiff) -> serde_json::Value {
+    let mut out = String::new();
+    write_json(&mut out, diff).unwrap();
+
+    serde_json::from_str(&out).unwrap()
+  }
+
+  #[test]
+  fn json_closures() {
+    let json = write(&closure_diff(Vec::new()));
+
+    assert_eq!(json["version"], 2_u32);
+    assert_eq!(
+      json["old"],
+      json!({
+        "path": "/nix/store/0004yybkm5hnwjyxv129js3mjp7kbrax-old-system",
+        "size": 1024_u64,
+        "unique_size": 512_u64,
+      }),
+    );
+    assert_eq!(
+      json["summary"]["paths"],
+      json!({ "old": 10_u64, "new": 11_u64 })
+    );
+    assert_eq!(json["packages"], json!([]));
+    assert_eq!(json.get("attribution"), None);
+    assert_eq!(json.get("rebuild_causes"), None);
+  }
+
+  #[test]
+  fn json_package_versions() {
+    let json = write(&closure_diff(vec![PackageDiff {
+      name:      "hello".to_owned(),
+      versions:  Diff {
+        old: vec![Version::from("2.12 ×2".to_owned())],
+        new: vec![
+          Version::from("2.13".to_owned()),
+          Version::from("<none>".to_owned()),
+        ],
+      },
+      sizes:     Diff {
+        old: Size::from_bytes(2_u64),
+        new: Size::from_bytes(1_u64),
+      },
+      status:    DiffStatus::Changed(Change::Upgraded),
+      selection: DerivationSelectionStatus::Selected,
+      unbuilt:   false,
+    }]));
+
+    assert_eq!(
+      json["packages"],
+      json!([{
+        "name": "hello",
+        "status": "upgraded",
+        "selection": "selected",
+        "old_versions": [{ "version": "2.12", "count": 2_u64 }],
+        "new_versions": [
+          { "version": "2.13", "count": 1_u64 },
+          { "version": null, "count": 1_u64 },
+        ],
+        "old_size": 2_u64,
+        "new_size": 1_u64,
+      }]),
+    );
+    assert_eq!(
+      json["summary"]["upgraded"],
+      json!({
+        "packages": 1_u64,
+        "selected": 1_u64,
+      })
+    );
+  }
+
+  #[test]
+  fn json_selection() {
+    let package = |name: &str, selection| {
+      PackageDiff {
+        name: name.to_owned(),
+        versions: Diff::default(),
+        sizes: Diff::default(),
+        status: DiffStatus::Added,
+        selection,
+        unbuilt: false,
+      }
+    };
+
+    let json = write(&closure_diff(vec![
+      package("bash", DerivationSelectionStatus::Unselected),
+      package("nano", DerivationSelectionStatus::NewlySelected),
+      package("vim", DerivationSelectionStatus::NewlyUnselected),
+    ]));
+
+    assert_eq!(json["packages"][0]["selection"], "unselected");
+    assert_eq!(json["packages"][1]["selection"], "newly-selected");
+    assert_eq!(json["packages"][2]["selection"], "newly-unselected");
+    assert_eq!(
+      json["summary"]["added"],
+      json!({
+        "packages": 3_u64,
+        "selected": 2_u64,
+      })
+    );
+  }
+
+  #[test]
+  fn json_unbuilt() {
+    let mut diff = closure_diff(vec![PackageDiff {
+      name:      "hello".to_owned(),
+      versions:  Diff {
+        old: Vec::new(),
+        new: vec![Version::from("2.13".to_owned())],
+      },
+      sizes:     Diff::default(),
+      status:    DiffStatus::Added,
+      selection: DerivationSelectionStatus::Unselected,
+      unbuilt:   true,
+    }]);
+    diff.unbuilt = true;
+
+    let json = write(&diff);
+
+    assert_eq!(json["new"]["size"], json!(null));
+    assert_eq!(json["new"]["unique_size"], json!(null));
+    assert_eq!(json["packages"][0]["new_size"], json!(null));
+    assert_eq!(json["packages"][0]["unbuilt"], true);
+  }
+}
@@ -0,0 +1,354 @@
+use std::{
+  cmp,
+  fmt,
+};
+
+use super::{
+  attribution_rows,
+  format_cause_versions,
+  format_counts,
+  format_size_change,
+  format_size_diff,
+};
+use crate::{
+  ClosureDiff,
+  DerivationSelectionStatus,
+  DiffStatus,
+  PackageDiff,
+  Version,
+};
+
+/// Escapes the characters of `text` that have a meaning in Markdown or would
+/// break out of a table cell.
+fn escape(text: &str) -> String {
+  let mut escaped = String::with_capacity(text.len());
+
+  for char in text.chars() {
+    if matches!(
+      char,
+      '\\' | '`' | '*' | '_' | '~' | '|' | '<' | '>' | '[' | ']' | '#'
+    ) {
+      escaped.push('\\');
+    }
+    escaped.push(char);
+  }
+
+  escaped
+}
+
+/// Writes `versions` separated by commas, each one wrapped in `delimiter`.
+fn write_versions(
+  writer: &mut impl fmt::Write,
+  versions: &[Version],
+  delimiter: &str,
+) -> fmt::Result {
+  for (index, version) in versions.iter().enumerate() {
+    if index > 0 {
+      write!(writer, ", ")?;
+    }
+
+    write!(
+      writer,
+      "{delimiter}{version}{delimiter}",
+      version = escape(version)
+    )?;
+  }
+
+  Ok(())
+}
+
+/// Writes the diff as Markdown, suitable for pull request and changelog
+/// comments.
+///
+/// Every status gets a collapsible section containing a table of its
+/// packages. Removed versions are struck through and added versions are
+/// bold, while system packages have their name in bold. If queried, the
+/// attribution of the size difference and the causes of rebuilds follow in
+/// collapsed tables.
+///
+/// # Errors
+///
+/// Returns `Err` when writing to `writer` fails.
+pub fn write_markdown(
+  writer: &mut impl fmt::Write,
+  diff: &ClosureDiff,
+) -> fmt::Result {
+  writeln!(
+    writer,
+    "`<<<` `{old}`\\\n`>>>` `{new}`",
+    old = diff.paths.old.display(),
+    new = diff.paths.new.display(),
+  )?;
+
+  for section in diff.packages.chunk_by(|this, that| {
+    // Using the Ord implementation instead of Eq on purpose, just like the
+    // text renderer.
+    this.status.cmp(&that.status) == cmp::Ordering::Equal
+  }) {
+    writeln!(
+      writer,
+      "\n<details open>\n<summary><b>{status}</b> ({count})</summary>\n",
+      status = match section[0].status {
+        DiffStatus::Changed(_) => "CHANGED",
+        DiffStatus::Added => "ADDED",
+        DiffStatus::Removed => "REMOVED",
+        DiffStatus::Rebuilt => "REBUILT",
+      },
+      count = section.len(),
+    )?;
+
+    writeln!(writer, "| | Package | Old | New | Size |")?;
+    writeln!(writer, "|-|-|-|-|-|")?;
+
+    for &PackageDiff {
+      ref name,
+      ref versions,
+      sizes,
+      status,
+      selection,
+      unbuilt,
+    } in section
+    {
+      let name_delimiter = match selection {
+        DerivationSelectionStatus::Selected
+        | DerivationSelectionStatus::NewlySelected => "**",
+        DerivationSelectionStatus::Unselected
+        | DerivationSelectionStatus::NewlyUnselected => "",
+      };
+
+      write!(
+        writer,
+        "| `{status}{selection}` | {name_delimiter}{name}{name_delimiter} | ",
+        status = status.char().value,
+        selection = selection.char().value,
+        name = escape(name),
+      )?;
+      if status == DiffStatus::Rebuilt {
+        write_versions(writer, &versions.old, "")?;
+        write!(writer, " | ")?;
+      } else {
+        write_versions(writer, &versions.old, "~~")?;
+        write!(writer, " | ")?;
+        write_versions(writer, &versions.new, "**")?;
+      }
+
+      let size_change = format_size_change(sizes.new - sizes.old);
+      match status {
+        _ if unbuilt => {
+          write!(writer, " | (not built)")?;
+        },
+        DiffStatus::Changed(_) | DiffStatus::Rebuilt => {
+          write!(
+            writer,
+            " | {size_old} -> {size_new} ({size_change})",
+            size_old = sizes.old,
+            size_new = sizes.new,
+          )?;
+        },
+        DiffStatus::Added | DiffStatus::Removed => {
+          write!(writer, " | {size_change}")?;
+        },
+      }
+
+      writeln!(writer, " |")?;
+    }
+
+    writeln!(writer, "\n</details>")?;
+  }
+
+  let summary = diff.summary();
+
+  writeln!(
+    writer,
+    "\n**PATHS:** {paths_old} -> {paths_new}\\\n**PACKAGES:** \
+     {packages}\\\n**SELECTED:** {selected}\\",
+    paths_old = summary.paths.old,
+    paths_new = summary.paths.new,
+    packages = format_counts(&summary, false),
+    selected = format_counts(&summary, true),
+  )?;
+
+  if diff.unbuilt {
+    writeln!(writer, "**SIZE:** unknown, some paths are not built")?;
+  } else {
+    let size_diff = diff.sizes.new - diff.sizes.old;
+
+    writeln!(
+      writer,
+      "**SIZE:** {size_old} -> {size_new}\\\n**DIFF:** \
+       {size_diff}\\\n**TRANSFER:** {unique_new} to copy, {unique_old} no \
+       longer needed",
+      size_old = diff.sizes.old,
+      size_new = diff.sizes.new,
+      unique_old = diff.unique_sizes.old,
+      unique_new = diff.unique_sizes.new,
+    )?;
+  }
+
+  if let Some(ref attribution) = diff.attribution {
+    writeln!(
+      writer,
+      "\n<details>\n<summary><b>ATTRIBUTION</b></summary>\n\n| Package | \
+       Exclusive | Shared |\n|-|-|-|",
+    )?;
+
+    for (name, exclusive, shared) in attribution_rows(attribution) {
+      writeln!(
+        writer,
+        "| {name} | {exclusive} | {shared} |",
+        name = escape(name),
+        exclusive = format_size_diff(exclusive),
+        shared = format_size_diff(shared),
+      )?;
+    }
+
+    writeln!(writer, "\n</details>")?;
+  }
+
+  if let Some(ref causes) = diff.rebuild_causes
+    && !causes.is_empty()
+  {
+    writeln!(
+      writer,
+      "\n<details>\n<summary><b>REBUILD CAUSES</b></summary>\n\n| Package | \
+       Versions | Rebuilds |\n|-|-|-|",
+    )?;
+
+    for cause in causes {
+      writeln!(
+        writer,
+        "| {name} | {versions} | {rebuilds} |",
+        name = escape(&cause.name),
+        versions = escape(&format_cause_versions(cause)),
+        rebuilds = cause.rebuilds,
+      )?;
+    }
+
+    writeln!(writer, "\n</details>")?;
+  }
+
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use size::Size;
+
+  use super::write_markdown;
+  use crate::{
+    Change,
+    ClosureDiff,
+    DerivationSelectionStatus,
+    Diff,
+    DiffStatus,
+    PackageDiff,
+    Version,
+    test_utils::store_path,
+  };
+
+  fn closure_diff(packages: Vec<PackageDiff>) -> ClosureDiff {
+    ClosureDiff {
+      paths: Diff {
+        old: store_path("old-system"),
+        new: store_path("new-system"),
+      },
+      sizes: Diff {
+        old: Size::from_bytes(1024_u64),
+        new: Size::from_bytes(2048_u64),
+      },
+      path_counts: Diff { old: 10, new: 11 },
+      unique_sizes: Diff {
+        old: Size::from_bytes(512_u64),
+        new: Size::from_bytes(1536_u64),
+      },
+      unbuilt: false,
+      packages,
+      attribution: None,
+      rebuild_causes: None,
+    }
+  }
+
+  fn write(diff: &ClosureDiff) -> String {
+    let mut out = String::new();
+    write_markdown(&mut out, diff).unwrap();
+    out
+  }
+
+  #[test]
+  fn markdown_changed_package() {
+    let out = write(&closure_diff(vec![PackageDiff {
+      name:      "firefox".to_owned(),
+      versions:  Diff {
+        old: vec![Version::from("120.0".to_owned())],
+        new: vec![Version::from("121.0".to_owned())],
+      },
+      sizes:     Diff {
+        old: Size::from_bytes(1024_u64),
+        new: Size::from_bytes(1536_u64),
+      },
+      status:    DiffStatus::Changed(Change::Upgraded),
+      selection: DerivationSelectionStatus::Selected,
+      unbuilt:   false,
+    }]));
+
+    assert!(out.starts_with(
+      "`<<<` `/nix/store/0004yybkm5hnwjyxv129js3mjp7kbrax-old-system`\\
+`>>>` `/nix/store/0004yybkm5hnwjyxv129js3mjp7kbrax-new-system`
+
+<details open>
+<summary><b>CHANGED</b> (1)</summary>
+
+| | Package | Old | New | Size |
+|-|-|-|-|-|
+| `U*` | **firefox** | ~~120.0~~ | **121.0** | 1.00 KiB -> 1.50 KiB (+512 \
+       bytes) |
+
+</details>
+"
+    ));
+  }
+
+  #[test]
+  fn markdown_escapes_names_and_versions() {
+    let out = write(&closure_diff(vec![PackageDiff {
+      name:      "etc_new".to_owned(),
+      versions:  Diff {
+        old: Vec::new(),
+        new: vec![Version::from("<none>".to_owned())],
+      },
+      sizes:     Diff::default(),
+      status:    DiffStatus::Added,
+      selection: DerivationSelectionStatus::Unselected,
+      unbuilt:   true,
+    }]));
+
+    assert!(
+      out.contains("| `A.` | etc\\_new |  | **\\<none\\>** | (not built) |\n")
+    );
+  }
+
+  #[test]
+  fn markdown_summary() {
+    let out = write(&closure_diff(Vec::new()));
+
+    assert!(out.ends_with(
+      "**PATHS:** 10 -> 11\\
+**PACKAGES:** 0 upgraded, 0 downgraded, 0 mixed, 0 added, 0 removed\\
+**SELECTED:** 0 upgraded, 0 downgraded, 0 mixed, 0 added, 0 removed\\
+**SIZE:** 1.00 KiB -> 2.00 KiB\\
+**DIFF:** 1.00 KiB\\
+**TRANSFER:** 1.50 KiB to copy, 512 bytes no longer needed
+"
+    ));
+  }
+
+  #[test]
+  fn markdown_unbuilt_size() {
+    let mut diff = closure_diff(Vec::new());
+    diff.unbuilt = true;
+
+    assert!(
+      write(&diff).ends_with("**SIZE:** unknown, some paths are not built\n")
+    );
+  }
+}
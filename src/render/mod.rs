@@ -0,0 +1,163 @@
+//! Renderers writing a [`ClosureDiff`](crate::ClosureDiff) in the formats
+//! supported by dix, and the reports of the other commands.
+
+use std::fmt::Write as _;
+
+use size::Size;
+
+use crate::{
+  Change,
+  Diff,
+  DiffStatus,
+  RebuildCause,
+  SizeAttribution,
+  StatusCount,
+  Summary,
+  Version,
+};
+
+mod highlight;
+
+mod html;
+pub use html::write_html;
+
+mod json;
+pub use json::{
+  JSON_SCHEMA_VERSION,
+  write_json,
+};
+
+mod markdown;
+pub use markdown::write_markdown;
+
+mod text;
+pub use text::{
+  write_attribution_diffln,
+  write_derivation_diff,
+  write_files_diff,
+  write_gc_impact,
+  write_packages_diffln,
+  write_paths_diffln,
+  write_rebuild_causes_diffln,
+  write_size_diffln,
+  write_summary_diffln,
+  write_text,
+  write_transfer_diffln,
+};
+
+/// Formats a size change with an explicit sign.
+fn format_size_change(size_diff: Size) -> String {
+  if size_diff.bytes() > 0 {
+    format!("+{size_diff}")
+  } else {
+    size_diff.to_string()
+  }
+}
+
+/// Returns the package counts of `summary` together with their status and
+/// label, in the order renderers write them.
+///
+/// Rebuilt packages are only shown on request, so their count is left out
+/// unless there are any.
+fn summary_counts(
+  summary: &Summary,
+) -> Vec<(DiffStatus, &'static str, StatusCount)> {
+  let mut counts = vec![
+    (
+      DiffStatus::Changed(Change::Upgraded),
+      "upgraded",
+      summary.upgraded,
+    ),
+    (
+      DiffStatus::Changed(Change::Downgraded),
+      "downgraded",
+      summary.downgraded,
+    ),
+    (
+      DiffStatus::Changed(Change::UpgradeDowngrade),
+      "mixed",
+      summary.upgrade_downgrade,
+    ),
+    (DiffStatus::Added, "added", summary.added),
+    (DiffStatus::Removed, "removed", summary.removed),
+  ];
+
+  if summary.rebuilt.packages > 0 {
+    counts.push((DiffStatus::Rebuilt, "rebuilt", summary.rebuilt));
+  }
+
+  counts
+}
+
+/// Formats the package counts of `summary` as a comma separated list, in
+/// total or only counting system packages.
+fn format_counts(summary: &Summary, selected_only: bool) -> String {
+  let mut counts = String::new();
+
+  for (index, (_, label, count)) in
+    summary_counts(summary).into_iter().enumerate()
+  {
+    let _ = write!(
+      counts,
+      "{separator}{count} {label}",
+      separator = if index > 0 { ", " } else { "" },
+      count = if selected_only {
+        count.selected
+      } else {
+        count.packages
+      },
+    );
+  }
+
+  counts
+}
+
+/// Formats the removed and added bytes of `sizes` together with their net
+/// change, like `+1.00 MiB (-2.00 MiB, +3.00 MiB)`.
+fn format_size_diff(sizes: Diff<Size>) -> String {
+  format!(
+    "{size_change} (-{size_old}, +{size_new})",
+    size_change = format_size_change(sizes.new - sizes.old),
+    size_old = sizes.old,
+    size_new = sizes.new,
+  )
+}
+
+/// Returns the rows renderers write for `attribution`: the name, exclusive
+/// and shared bytes of every system package, followed by the bytes shared by
+/// several packages and those no package depends on.
+fn attribution_rows(
+  attribution: &SizeAttribution,
+) -> Vec<(&str, Diff<Size>, Diff<Size>)> {
+  attribution
+    .packages
+    .iter()
+    .map(|package| (package.name.as_str(), package.exclusive, package.shared))
+    .chain([
+      ("<shared>", attribution.shared, Diff::default()),
+      ("<other>", attribution.unattributed, Diff::default()),
+    ])
+    .collect()
+}
+
+/// Formats the versions of the package of `cause`, like `3.0.13 -> 3.0.14`,
+/// or just `2.40` if the package was rebuilt at the same version.
+fn format_cause_versions(cause: &RebuildCause) -> String {
+  let join = |versions: &[Version]| {
+    versions
+      .iter()
+      .map(ToString::to_string)
+      .collect::<Vec<_>>()
+      .join(", ")
+  };
+
+  if cause.versions.old.is_empty() || cause.versions.old == cause.versions.new {
+    join(&cause.versions.new)
+  } else {
+    format!(
+      "{old} -> {new}",
+      old = join(&cause.versions.old),
+      new = join(&cause.versions.new),
+    )
+  }
+}
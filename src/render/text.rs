@@ -0,0 +1,923 @@
+use std::{
+  cmp,
+  fmt,
+  path::Path,
+};
+
+use size::Size;
+use unicode_width::UnicodeWidthStr as _;
+use yansi::{
+  Paint as _,
+  Painted,
+};
+
+use super::{
+  attribution_rows,
+  format_cause_versions,
+  format_size_change,
+  highlight::{
+    Highlight,
+    Segment,
+    highlight_versions,
+  },
+  summary_counts,
+};
+use crate::{
+  Change,
+  ClosureDiff,
+  ClosureSource,
+  DerivationDiff,
+  DerivationSelectionStatus,
+  Diff,
+  DiffStatus,
+  FileEntry,
+  FilesDiff,
+  GcImpact,
+  PackageDiff,
+  RebuildCause,
+  SizeAttribution,
+  StorePath,
+  Summary,
+  Version,
+};
+
+impl DiffStatus {
+  pub(crate) fn char(self) -> Painted<&'static char> {
+    match self {
+      Self::Changed(Change::UpgradeDowngrade) => 'C'.yellow().bold(),
+      Self::Changed(Change::Upgraded) => 'U'.bright_cyan().bold(),
+      Self::Changed(Change::Downgraded) => 'D'.magenta().bold(),
+      Self::Added => 'A'.green().bold(),
+      Self::Removed => 'R'.red().bold(),
+      Self::Rebuilt => 'B'.blue().bold(),
+    }
+  }
+}
+
+impl DerivationSelectionStatus {
+  pub(crate) fn char(self) -> Painted<&'static char> {
+    match self {
+      Self::Selected => '*'.bold(),
+      Self::NewlySelected => '+'.bold(),
+      Self::Unselected => Painted::new(&'.'),
+      Self::NewlyUnselected => Painted::new(&'-'),
+    }
+  }
+}
+
+/// Writes the diff header (<<< out, >>>in) followed by an empty line.
+fn write_header(
+  writer: &mut impl fmt::Write,
+  paths: &Diff<StorePath>,
+) -> fmt::Result {
+  writeln!(
+    writer,
+    "{arrows} {old}",
+    arrows = "<<<".bold(),
+    old = paths.old.display(),
+  )?;
+  writeln!(
+    writer,
+    "{arrows} {new}",
+    arrows = ">>>".bold(),
+    new = paths.new.display(),
+  )?;
+
+  writeln!(writer)
+}
+
+/// Writes the diff header (<<< out, >>>in) and package diff.
+///
+/// A shorthand for [`ClosureDiff::query_packages`] and
+/// [`write_packages_diffln`] kept for library users from before the diff was
+/// split from its rendering. Use [`ClosureDiff`] and [`write_text`] to also
+/// write the summary and sizes.
+///
+/// # Returns
+///
+/// Will return the amount of package diffs written. Even when zero,
+/// the header will be written.
+#[expect(clippy::missing_errors_doc)]
+pub fn write_paths_diffln(
+  writer: &mut impl fmt::Write,
+  source_old: &dyn ClosureSource,
+  path_old: &StorePath,
+  source_new: &dyn ClosureSource,
+  path_new: &StorePath,
+) -> anyhow::Result<usize> {
+  let diff =
+    ClosureDiff::query_packages(source_old, path_old, source_new, path_new)?;
+
+  write_header(writer, &diff.paths)?;
+
+  Ok(write_packages_diffln(writer, &diff.packages)?)
+}
+
+/// Writes the diff header (<<< out, >>>in), the package diff, the summary,
+/// the size difference, the transfer size and, if queried, the attribution of
+/// the size difference and the causes of rebuilds.
+///
+/// The sizes are left out if some paths are not built, since their sizes are
+/// unknown.
+///
+/// # Errors
+///
+/// Returns `Err` when writing to `writer` fails.
+pub fn write_text(
+  writer: &mut impl fmt::Write,
+  diff: &ClosureDiff,
+) -> fmt::Result {
+  write_header(writer, &diff.paths)?;
+
+  if write_packages_diffln(writer, &diff.packages)? > 0 {
+    writeln!(writer)?;
+  }
+
+  write_summary_diffln(writer, &diff.summary())?;
+  if diff.unbuilt {
+    writeln!(
+      writer,
+      "{header}: {unknown}",
+      header = "SIZE".bold(),
+      unknown = "unknown, some paths are not built".yellow(),
+    )?;
+  } else {
+    write_size_diffln(writer, diff.sizes.old, diff.sizes.new)?;
+    write_transfer_diffln(writer, diff.unique_sizes)?;
+  }
+
+  if let Some(ref attribution) = diff.attribution {
+    writeln!(writer)?;
+    write_attribution_diffln(writer, attribution)?;
+  }
+
+  if let Some(ref causes) = diff.rebuild_causes
+    && !causes.is_empty()
+  {
+    writeln!(writer)?;
+    write_rebuild_causes_diffln(writer, causes)?;
+  }
+
+  Ok(())
+}
+
+fn paint(highlight: Highlight, text: &str) -> Painted<&str> {
+  match highlight {
+    Highlight::Plain => Painted::new(text),
+    Highlight::Removed => text.red(),
+    Highlight::Added => text.green(),
+    Highlight::Unchanged => text.yellow(),
+  }
+}
+
+/// Writes highlighted version segments preceded by a space, or nothing if
+/// there are none.
+fn write_segments(
+  writer: &mut impl fmt::Write,
+  segments: &[Segment],
+) -> fmt::Result {
+  if segments.is_empty() {
+    return Ok(());
+  }
+
+  write!(writer, " ")?;
+
+  for &(highlight, ref text) in segments {
+    write!(writer, "{text}", text = paint(highlight, text))?;
+  }
+
+  Ok(())
+}
+
+/// Returns the width of the versions as written by [`write_packages_diffln`].
+fn versions_width(versions: &Diff<Vec<Segment>>) -> usize {
+  let width = |segments: &[Segment]| {
+    segments
+      .iter()
+      .map(|segment| segment.1.width())
+      .sum::<usize>()
+  };
+
+  match (versions.old.is_empty(), versions.new.is_empty()) {
+    (true, true) => 0,
+    (false, true) => 1 + width(&versions.old),
+    (true, false) => 1 + width(&versions.new),
+    (false, false) => 1 + width(&versions.old) + 4 + width(&versions.new),
+  }
+}
+
+/// Writes the net change of `sizes` followed by the removed and added bytes.
+fn write_size_change(
+  writer: &mut impl fmt::Write,
+  sizes: Diff<Size>,
+  width: usize,
+) -> fmt::Result {
+  let size_diff = sizes.new - sizes.old;
+  let size_change = format!("{:>width$}", format_size_change(size_diff));
+
+  write!(
+    writer,
+    "{size_change} (-{size_old}, +{size_new})",
+    size_change = if size_diff.bytes() > 0 {
+      size_change.green()
+    } else {
+      size_change.red()
+    },
+    size_old = sizes.old.red(),
+    size_new = sizes.new.green(),
+  )
+}
+
+/// Writes the NAR size of a package in both closures and how much it changed.
+fn write_package_size(
+  writer: &mut impl fmt::Write,
+  sizes: Diff<Size>,
+  status: DiffStatus,
+) -> fmt::Result {
+  let size_diff = sizes.new - sizes.old;
+  let size_change = format_size_change(size_diff);
+  let size_change = if size_diff.bytes() > 0 {
+    size_change.green()
+  } else {
+    size_change.red()
+  };
+
+  match status {
+    DiffStatus::Changed(_) | DiffStatus::Rebuilt => {
+      write!(
+        writer,
+        "{size_old} -> {size_new} ({size_change})",
+        size_old = sizes.old.red(),
+        size_new = sizes.new.green(),
+      )
+    },
+    DiffStatus::Added | DiffStatus::Removed => {
+      write!(writer, "{size_change}")
+    },
+  }
+}
+
+/// Writes the package diff, grouped into sections by status.
+///
+/// Every package is followed by its size change, aligned in a column after
+/// the versions, or by whether it is not built yet.
+///
+/// # Returns
+///
+/// Will return the amount of package diffs written.
+///
+/// # Errors
+///
+/// Returns `Err` when writing to `writer` fails.
+pub fn write_packages_diffln(
+  writer: &mut impl fmt::Write,
+  diffs: &[PackageDiff],
+) -> Result<usize, fmt::Error> {
+  let name_width = diffs
+    .iter()
+    .map(|diff| diff.name.width())
+    .max()
+    .unwrap_or(0);
+
+  let versions = diffs
+    .iter()
+    .map(|diff| highlight_versions(&diff.versions))
+    .collect::<Vec<_>>();
+
+  let versions_column_width =
+    versions.iter().map(versions_width).max().unwrap_or(0);
+
+  let mut last_status = None::<DiffStatus>;
+
+  for (
+    &PackageDiff {
+      ref name,
+      sizes,
+      status,
+      selection,
+      unbuilt,
+      ..
+    },
+    versions,
+  ) in diffs.iter().zip(versions)
+  {
+    if last_status.is_none_or(|last_status| {
+      // Using the Ord implementation instead of Eq on purpose.
+      // Eq returns false for DiffStatus::Changed(X) == DiffStatus::Changed(Y).
+      last_status.cmp(&status) != cmp::Ordering::Equal
+    }) {
+      writeln!(
+        writer,
+        "{nl}{status}",
+        nl = if last_status.is_some() { "\n" } else { "" },
+        status = match status {
+          DiffStatus::Changed(_) => "CHANGED",
+          DiffStatus::Added => "ADDED",
+          DiffStatus::Removed => "REMOVED",
+          DiffStatus::Rebuilt => "REBUILT",
+        }
+        .bold(),
+      )?;
+
+      last_status = Some(status);
+    }
+
+    write!(
+      writer,
+      "[{status_char}{selection}] {name:<name_width$}",
+      status_char = status.char(),
+      selection = selection.char(),
+      name = name.paint(selection.char().style),
+    )?;
+
+    write_segments(writer, &versions.old)?;
+    if !versions.old.is_empty() && !versions.new.is_empty() {
+      write!(writer, " ->")?;
+    }
+    write_segments(writer, &versions.new)?;
+
+    write!(
+      writer,
+      "{padding}  ",
+      padding = " ".repeat(versions_column_width - versions_width(&versions)),
+    )?;
+    if unbuilt {
+      write!(writer, "{unbuilt}", unbuilt = "(not built)".yellow())?;
+    } else {
+      write_package_size(writer, sizes, status)?;
+    }
+
+    writeln!(writer)?;
+  }
+
+  Ok(diffs.len())
+}
+
+/// Writes the amount of paths in both closures and the amount of packages of
+/// every status, in total and only counting system packages.
+///
+/// # Errors
+///
+/// Returns `Err` when writing to `writer` fails.
+pub fn write_summary_diffln(
+  writer: &mut impl fmt::Write,
+  summary: &Summary,
+) -> fmt::Result {
+  writeln!(
+    writer,
+    "{header}: {paths_old} -> {paths_new}",
+    header = "PATHS".bold(),
+    paths_old = summary.paths.old,
+    paths_new = summary.paths.new,
+  )?;
+
+  for (header, selected_only) in [("PACKAGES", false), ("SELECTED", true)] {
+    write!(writer, "{header}:", header = header.bold())?;
+
+    for (index, (status, label, count)) in
+      summary_counts(summary).into_iter().enumerate()
+    {
+      let count = if selected_only {
+        count.selected
+      } else {
+        count.packages
+      };
+
+      write!(
+        writer,
+        "{separator} {count} {label}",
+        separator = if index > 0 { "," } else { "" },
+        count = count.paint(status.char().style),
+      )?;
+    }
+
+    writeln!(writer)?;
+  }
+
+  Ok(())
+}
+
+/// Writes the size difference between two numbers to `writer`.
+///
+/// # Returns
+///
+/// Will return nothing when successful.
+///
+/// # Errors
+///
+/// Returns `Err` when writing to `writer` fails.
+pub fn write_size_diffln(
+  writer: &mut impl fmt::Write,
+  size_old: Size,
+  size_new: Size,
+) -> fmt::Result {
+  let size_diff = size_new - size_old;
+
+  writeln!(
+    writer,
+    "{header}: {size_old} -> {size_new}",
+    header = "SIZE".bold(),
+    size_old = size_old.red(),
+    size_new = size_new.green(),
+  )?;
+
+  writeln!(
+    writer,
+    "{header}: {size_diff}",
+    header = "DIFF".bold(),
+    size_diff = if size_diff.bytes() > 0 {
+      size_diff.green()
+    } else {
+      size_diff.red()
+    },
+  )
+}
+
+/// Writes the NAR size of the paths only in the new closure, which have to be
+/// copied to switch to it, and of the paths only in the old closure.
+///
+/// Unlike the net difference written by [`write_size_diffln`], this shows how
+/// much actually changed when paths of similar size are replaced.
+///
+/// # Errors
+///
+/// Returns `Err` when writing to `writer` fails.
+pub fn write_transfer_diffln(
+  writer: &mut impl fmt::Write,
+  unique_sizes: Diff<Size>,
+) -> fmt::Result {
+  writeln!(
+    writer,
+    "{header}: {size_new} to copy, {size_old} no longer needed",
+    header = "TRANSFER".bold(),
+    size_new = unique_sizes.new.green(),
+    size_old = unique_sizes.old.red(),
+  )
+}
+
+/// Writes which system packages the size difference is caused by, ranked by
+/// how much the paths only they depend on grew or shrank.
+///
+/// Paths several system packages depend on are counted for each of them
+/// after `shared`, and once in the `<shared>` row.
+///
+/// # Errors
+///
+/// Returns `Err` when writing to `writer` fails.
+pub fn write_attribution_diffln(
+  writer: &mut impl fmt::Write,
+  attribution: &SizeAttribution,
+) -> fmt::Result {
+  let rows = attribution_rows(attribution);
+
+  let name_width = rows
+    .iter()
+    .map(|&(name, ..)| name.width())
+    .max()
+    .unwrap_or(0);
+  let change_width = rows
+    .iter()
+    .map(|&(_, exclusive, _)| {
+      format_size_change(exclusive.new - exclusive.old).width()
+    })
+    .max()
+    .unwrap_or(0);
+
+  writeln!(writer, "{header}:", header = "ATTRIBUTION".bold())?;
+
+  for (name, exclusive, shared) in rows {
+    write!(writer, "{name:<name_width$}  ")?;
+    write_size_change(writer, exclusive, change_width)?;
+
+    if shared != Diff::default() {
+      write!(writer, ", shared ")?;
+      write_size_change(writer, shared, 0)?;
+    }
+
+    writeln!(writer)?;
+  }
+
+  Ok(())
+}
+
+/// Writes the changed paths explaining why other paths were rebuilt at the
+/// same version, like `openssl 3.0.13 -> 3.0.14  caused 812 rebuilds`.
+///
+/// # Errors
+///
+/// Returns `Err` when writing to `writer` fails.
+pub fn write_rebuild_causes_diffln(
+  writer: &mut impl fmt::Write,
+  causes: &[RebuildCause],
+) -> fmt::Result {
+  let rows = causes
+    .iter()
+    .map(|cause| (cause.name.as_str(), format_cause_versions(cause)))
+    .collect::<Vec<_>>();
+
+  let name_width = rows.iter().map(|row| row.0.width()).max().unwrap_or(0);
+  let versions_width = rows.iter().map(|row| row.1.width()).max().unwrap_or(0);
+
+  writeln!(writer, "{header}:", header = "REBUILD CAUSES".bold())?;
+
+  for (cause, (name, versions)) in causes.iter().zip(rows) {
+    writeln!(
+      writer,
+      "{name:<name_width$} {versions:<versions_width$}  caused {rebuilds} \
+       {noun}",
+      versions = versions.yellow(),
+      rebuilds = cause.rebuilds.bold(),
+      noun = if cause.rebuilds == 1 {
+        "rebuild"
+      } else {
+        "rebuilds"
+      },
+    )?;
+  }
+
+  Ok(())
+}
+
+/// Writes the removed and added `items` below `header`, if there are any.
+fn write_list_diffln(
+  writer: &mut impl fmt::Write,
+  header: &str,
+  items: &Diff<Vec<String>>,
+) -> fmt::Result {
+  if items.old.is_empty() && items.new.is_empty() {
+    return Ok(());
+  }
+
+  writeln!(writer)?;
+  writeln!(writer, "{header}:", header = header.bold())?;
+
+  for item in &items.old {
+    writeln!(writer, "{sign} {item}", sign = '-'.red().bold())?;
+  }
+  for item in &items.new {
+    writeln!(writer, "{sign} {item}", sign = '+'.green().bold())?;
+  }
+
+  Ok(())
+}
+
+/// Writes what differs between two derivations of a package: the input
+/// derivations and sources, the system, the builder and its arguments, and
+/// the environment variables.
+///
+/// Environment variable values are quoted, so multi-line scripts stay on a
+/// single line.
+///
+/// # Errors
+///
+/// Returns `Err` when writing to `writer` fails.
+pub fn write_derivation_diff(
+  writer: &mut impl fmt::Write,
+  diff: &DerivationDiff,
+) -> fmt::Result {
+  writeln!(
+    writer,
+    "{arrows} {old}",
+    arrows = "<<<".bold(),
+    old = diff.paths.old.display(),
+  )?;
+  writeln!(
+    writer,
+    "{arrows} {new}",
+    arrows = ">>>".bold(),
+    new = diff.paths.new.display(),
+  )?;
+
+  write_list_diffln(writer, "INPUT DERIVATIONS", &diff.input_derivations)?;
+  write_list_diffln(writer, "INPUT SOURCES", &diff.input_sources)?;
+
+  for (header, value) in [("SYSTEM", &diff.system), ("BUILDER", &diff.builder)]
+  {
+    if let Some(ref value) = *value {
+      writeln!(writer)?;
+      writeln!(
+        writer,
+        "{header}: {old} -> {new}",
+        header = header.bold(),
+        old = value.old.red(),
+        new = value.new.green(),
+      )?;
+    }
+  }
+
+  if let Some(ref args) = diff.args {
+    writeln!(writer)?;
+    writeln!(writer, "{header}:", header = "ARGS".bold())?;
+    writeln!(
+      writer,
+      "{sign} {old:?}",
+      sign = '-'.red().bold(),
+      old = args.old
+    )?;
+    writeln!(
+      writer,
+      "{sign} {new:?}",
+      sign = '+'.green().bold(),
+      new = args.new,
+    )?;
+  }
+
+  if !diff.env.is_empty() {
+    writeln!(writer)?;
+    writeln!(writer, "{header}:", header = "ENV".bold())?;
+
+    for variable in &diff.env {
+      let (ref name, ref values) = *variable;
+
+      writeln!(writer, "{name}", name = name.bold())?;
+
+      if let Some(ref old) = values.old {
+        writeln!(writer, "{sign} {old:?}", sign = '-'.red().bold())?;
+      }
+      if let Some(ref new) = values.new {
+        writeln!(writer, "{sign} {new:?}", sign = '+'.green().bold())?;
+      }
+    }
+  }
+
+  Ok(())
+}
+
+/// Describes a file, symlink or directory of a store path on a single line.
+fn format_file_entry(entry: &FileEntry) -> String {
+  match *entry {
+    FileEntry::File {
+      size,
+      executable,
+      ref hash,
+    } => {
+      let mut description = size.to_string();
+
+      if executable {
+        description.push_str(", executable");
+      }
+
+      if let Some(ref hash) = *hash {
+        description.push_str(", sha256:");
+        description.push_str(hash);
+      }
+
+      description
+    },
+    FileEntry::Symlink { ref target } => {
+      format!("symlink to {target}", target = target.display())
+    },
+    FileEntry::Directory => "directory".to_owned(),
+  }
+}
+
+/// Writes the files added to, removed from and modified between two store
+/// paths of a package, followed by how many there are of each.
+///
+/// Modified files are described by their old and new size, and their hashes
+/// if the sizes are the same. Modified symlinks are described by their old
+/// and new targets.
+///
+/// # Errors
+///
+/// Returns `Err` when writing to `writer` fails.
+pub fn write_files_diff(
+  writer: &mut impl fmt::Write,
+  diff: &FilesDiff,
+) -> fmt::Result {
+  writeln!(
+    writer,
+    "{arrows} {old}",
+    arrows = "<<<".bold(),
+    old = diff.paths.old.display(),
+  )?;
+  writeln!(
+    writer,
+    "{arrows} {new}",
+    arrows = ">>>".bold(),
+    new = diff.paths.new.display(),
+  )?;
+
+  // The store path itself has an empty relative path.
+  let display = |path: &Path| {
+    if path.as_os_str().is_empty() {
+      ".".to_owned()
+    } else {
+      path.display().to_string()
+    }
+  };
+
+  let mut added = Vec::new();
+  let mut removed = Vec::new();
+  let mut modified = Vec::new();
+
+  for change in &diff.changes {
+    match (change.entries.old.as_ref(), change.entries.new.as_ref()) {
+      (None, Some(new)) => added.push((display(&change.path), new)),
+      (Some(old), None) => removed.push((display(&change.path), old)),
+      (Some(old), Some(new)) => {
+        modified.push((display(&change.path), Diff { old, new }));
+      },
+      (None, None) => {},
+    }
+  }
+
+  for (header, sign, entries) in [
+    ("ADDED", '+'.green().bold(), &added),
+    ("REMOVED", '-'.red().bold(), &removed),
+  ] {
+    if entries.is_empty() {
+      continue;
+    }
+
+    writeln!(writer)?;
+    writeln!(writer, "{header}:", header = header.bold())?;
+
+    let path_width = entries
+      .iter()
+      .map(|entry| entry.0.width())
+      .max()
+      .unwrap_or(0);
+
+    for &(ref path, entry) in entries {
+      writeln!(
+        writer,
+        "{sign} {path:<path_width$}  {entry}",
+        entry = format_file_entry(entry),
+      )?;
+    }
+  }
+
+  if !modified.is_empty() {
+    writeln!(writer)?;
+    writeln!(writer, "{header}:", header = "MODIFIED".bold())?;
+  }
+
+  for entry in &modified {
+    let (ref path, ref entries) = *entry;
+
+    write!(writer, "{path}", path = path.bold())?;
+
+    if let (Some(size_old), Some(size_new)) =
+      (entries.old.file_size(), entries.new.file_size())
+      && size_old != size_new
+    {
+      write!(
+        writer,
+        " ({change})",
+        change = format_size_change(size_new - size_old),
+      )?;
+    }
+    writeln!(writer)?;
+
+    writeln!(
+      writer,
+      "{sign} {old}",
+      sign = '-'.red().bold(),
+      old = format_file_entry(entries.old),
+    )?;
+    writeln!(
+      writer,
+      "{sign} {new}",
+      sign = '+'.green().bold(),
+      new = format_file_entry(entries.new),
+    )?;
+  }
+
+  writeln!(writer)?;
+  writeln!(
+    writer,
+    "{header}: {added} added, {removed} removed, {modified} modified",
+    header = "FILES".bold(),
+    added = added.len(),
+    removed = removed.len(),
+    modified = modified.len(),
+  )
+}
+
+/// Writes how much store space deleting a generation would reclaim, followed
+/// by the reclaimable paths, biggest first.
+///
+/// # Errors
+///
+/// Returns `Err` when writing to `writer` fails.
+pub fn write_gc_impact(
+  writer: &mut impl fmt::Write,
+  impact: &GcImpact,
+) -> fmt::Result {
+  writeln!(
+    writer,
+    "{arrows} {generation}",
+    arrows = "<<<".bold(),
+    generation = impact.generation.display(),
+  )?;
+  writeln!(writer)?;
+
+  let paths = impact
+    .reclaimable
+    .iter()
+    .map(|&(ref path, nar_size)| {
+      let (name, version) = match path.parse_name_and_version() {
+        Ok((name, version)) => {
+          (
+            name.to_owned(),
+            version.unwrap_or_else(|| Version::from("<none>".to_owned())),
+          )
+        },
+        Err(error) => {
+          log::warn!("error parsing path name and version: {error}");
+          (
+            path.to_string_lossy().into_owned(),
+            Version::from(String::new()),
+          )
+        },
+      };
+
+      (name, version, nar_size)
+    })
+    .collect::<Vec<_>>();
+
+  if !paths.is_empty() {
+    let name_width = paths.iter().map(|path| path.0.width()).max().unwrap_or(0);
+    let version_width =
+      paths.iter().map(|path| path.1.width()).max().unwrap_or(0);
+
+    writeln!(writer, "{header}", header = "RECLAIMABLE".bold())?;
+
+    for (name, version, nar_size) in paths {
+      writeln!(
+        writer,
+        "{name:<name_width$} {version:<version_width$}  {nar_size}",
+        nar_size = nar_size.red(),
+      )?;
+    }
+
+    writeln!(writer)?;
+  }
+
+  writeln!(
+    writer,
+    "{header}: {roots}",
+    header = "ROOTS".bold(),
+    roots = impact.roots,
+  )?;
+  writeln!(
+    writer,
+    "{header}: {reclaimable} of {paths} reclaimable",
+    header = "PATHS".bold(),
+    reclaimable = impact.reclaimable.len(),
+    paths = impact.path_count,
+  )?;
+  writeln!(
+    writer,
+    "{header}: {reclaimable} of {size} reclaimable",
+    header = "SIZE".bold(),
+    reclaimable = impact.reclaimable_size().red(),
+    size = impact.size,
+  )
+}
+
+#[cfg(test)]
+mod tests {
+  use size::Size;
+
+  use super::write_paths_diffln;
+  use crate::{
+    Dump,
+    PathInfo,
+    test_utils::store_path,
+  };
+
+  #[test]
+  fn paths_diff() {
+    yansi::disable();
+
+    let dump = |name: &str| {
+      Dump::from_iter([PathInfo {
+        path:       store_path(name),
+        references: Vec::new(),
+        nar_size:   Size::from_bytes(1_u64),
+        deriver:    None,
+      }])
+    };
+
+    let mut out = String::new();
+    let count = write_paths_diffln(
+      &mut out,
+      &dump("hello-2.12"),
+      &store_path("hello-2.12"),
+      &dump("hello-2.13"),
+      &store_path("hello-2.13"),
+    )
+    .unwrap();
+
+    assert_eq!(count, 1);
+    assert!(out.starts_with(
+      "<<< /nix/store/0004yybkm5hnwjyxv129js3mjp7kbrax-hello-2.12
+>>> /nix/store/0004yybkm5hnwjyxv129js3mjp7kbrax-hello-2.13
+
+CHANGED
+[U.] hello 2.12 -> 2.13"
+    ));
+  }
+}
@@ -0,0 +1,275 @@
+use std::{
+  cmp,
+  str,
+};
+
+use anyhow::{
+  Error,
+  Result,
+  bail,
+};
+use itertools::{
+  EitherOrBoth,
+  Itertools as _,
+};
+
+use crate::{
+  Diff,
+  PackageDiff,
+  Version,
+};
+
+/// How the packages of each status are ordered. Packages are always grouped
+/// by status first, so the sections of the output stay intact.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SortOrder {
+  /// By name.
+  #[default]
+  Name,
+  /// By how much the package's own store paths grew or shrank, biggest
+  /// changes first. Dependencies the package pulls in are not counted.
+  PackageSize,
+  /// By how significant the version change is, so major version changes
+  /// come before patch releases.
+  Version,
+  /// System packages first.
+  Selection,
+}
+
+impl str::FromStr for SortOrder {
+  type Err = Error;
+
+  fn from_str(order: &str) -> Result<Self> {
+    Ok(match order {
+      "name" => Self::Name,
+      "package-size" => Self::PackageSize,
+      "version" => Self::Version,
+      "selection" => Self::Selection,
+      _ => {
+        bail!(
+          "invalid sort order '{order}', expected one of name, package-size, \
+           version or selection"
+        )
+      },
+    })
+  }
+}
+
+/// Returns the index of the first component that differs between two
+/// versions, so a lower depth means a more significant change.
+fn version_change_depth(old: &Version, new: &Version) -> usize {
+  old
+    .iter()
+    .filter_map(Result::ok)
+    .zip_longest(new.iter().filter_map(Result::ok))
+    .position(|components| {
+      match components {
+        EitherOrBoth::Both(old, new) => old != new,
+        EitherOrBoth::Left(_) | EitherOrBoth::Right(_) => true,
+      }
+    })
+    .unwrap_or(usize::MAX)
+}
+
+/// Returns the most significant change between the versions of a package.
+/// Added and removed packages have no version change and sort last.
+fn versions_change_depth(versions: &Diff<Vec<Version>>) -> usize {
+  versions
+    .old
+    .iter()
+    .zip(&versions.new)
+    .map(|(old, new)| version_change_depth(old, new))
+    .min()
+    .unwrap_or(usize::MAX)
+}
+
+impl SortOrder {
+  /// Sorts `packages` by status, then by this order and finally by name.
+  pub fn sort(self, packages: &mut [PackageDiff]) {
+    packages.sort_by(|this, that| {
+      this
+        .status
+        .cmp(&that.status)
+        .then_with(|| {
+          match self {
+            Self::Name => cmp::Ordering::Equal,
+            Self::PackageSize => {
+              let impact = |package: &PackageDiff| {
+                (package.sizes.new.bytes() - package.sizes.old.bytes()).abs()
+              };
+
+              impact(that).cmp(&impact(this))
+            },
+            Self::Version => {
+              versions_change_depth(&this.versions)
+                .cmp(&versions_change_depth(&that.versions))
+            },
+            Self::Selection => {
+              that
+                .selection
+                .is_selected()
+                .cmp(&this.selection.is_selected())
+            },
+          }
+        })
+        .then_with(|| this.name.cmp(&that.name))
+    });
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use size::Size;
+
+  use super::SortOrder;
+  use crate::{
+    Change,
+    DerivationSelectionStatus,
+    Diff,
+    DiffStatus,
+    PackageDiff,
+    Version,
+  };
+
+  fn package(
+    name: &str,
+    versions: (&str, &str),
+    sizes: (u64, u64),
+    selection: DerivationSelectionStatus,
+  ) -> PackageDiff {
+    PackageDiff {
+      name: name.to_owned(),
+      versions: Diff {
+        old: vec![Version::from(versions.0.to_owned())],
+        new: vec![Version::from(versions.1.to_owned())],
+      },
+      sizes: Diff {
+        old: Size::from_bytes(sizes.0),
+        new: Size::from_bytes(sizes.1),
+      },
+      status: DiffStatus::Changed(Change::Upgraded),
+      selection,
+      unbuilt: false,
+    }
+  }
+
+  fn names(packages: &[PackageDiff]) -> Vec<&str> {
+    packages
+      .iter()
+      .map(|package| package.name.as_str())
+      .collect()
+  }
+
+  #[test]
+  fn sort_by_name() {
+    let mut packages = vec![
+      package(
+        "awk",
+        ("5.2", "5.3"),
+        (10, 10),
+        DerivationSelectionStatus::Selected,
+      ),
+      package(
+        "bash",
+        ("5.2.15", "5.2.26"),
+        (100, 110),
+        DerivationSelectionStatus::Unselected,
+      ),
+    ];
+    packages[0].status = DiffStatus::Added;
+
+    "name".parse::<SortOrder>().unwrap().sort(&mut packages);
+
+    // The status still comes first.
+    assert_eq!(names(&packages), ["bash", "awk"]);
+  }
+
+  #[test]
+  fn sort_by_package_size() {
+    let mut packages = vec![
+      package(
+        "bash",
+        ("5.2.15", "5.2.26"),
+        (100, 110),
+        DerivationSelectionStatus::Unselected,
+      ),
+      package(
+        "firefox",
+        ("120.0", "121.0"),
+        (1000, 900),
+        DerivationSelectionStatus::Unselected,
+      ),
+      package(
+        "vim",
+        ("9.0", "9.1"),
+        (10, 10),
+        DerivationSelectionStatus::Selected,
+      ),
+    ];
+
+    "package-size"
+      .parse::<SortOrder>()
+      .unwrap()
+      .sort(&mut packages);
+
+    assert_eq!(names(&packages), ["firefox", "bash", "vim"]);
+  }
+
+  #[test]
+  fn sort_by_version() {
+    let mut packages = vec![
+      package(
+        "bash",
+        ("5.2.15", "5.2.26"),
+        (100, 110),
+        DerivationSelectionStatus::Unselected,
+      ),
+      package(
+        "vim",
+        ("9.0", "9.1"),
+        (10, 10),
+        DerivationSelectionStatus::Selected,
+      ),
+      package(
+        "firefox",
+        ("120.0", "121.0"),
+        (1000, 900),
+        DerivationSelectionStatus::Unselected,
+      ),
+    ];
+
+    "version".parse::<SortOrder>().unwrap().sort(&mut packages);
+
+    assert_eq!(names(&packages), ["firefox", "vim", "bash"]);
+  }
+
+  #[test]
+  fn sort_by_selection() {
+    let mut packages = vec![
+      package(
+        "bash",
+        ("5.2.15", "5.2.26"),
+        (100, 110),
+        DerivationSelectionStatus::Unselected,
+      ),
+      package(
+        "vim",
+        ("9.0", "9.1"),
+        (10, 10),
+        DerivationSelectionStatus::NewlySelected,
+      ),
+    ];
+
+    "selection"
+      .parse::<SortOrder>()
+      .unwrap()
+      .sort(&mut packages);
+
+    assert_eq!(names(&packages), ["vim", "bash"]);
+  }
+
+  #[test]
+  fn invalid_sort_order() {
+    "size".parse::<SortOrder>().unwrap_err();
+  }
+}
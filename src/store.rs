@@ -1,12 +1,15 @@
 #![allow(clippy::mem_forget)]
 
 use std::{
+  collections::{
+    BTreeMap,
+    HashMap,
+  },
   iter::{
     FilterMap,
     Iterator,
     Peekable,
   },
-  path::Path,
 };
 
 use anyhow::{
@@ -25,13 +28,22 @@ use rusqlite::{
 use size::Size;
 
 use crate::{
+  ClosureSource,
+  Derivation,
   DerivationId,
+  PathInfo,
+  StoreDirs,
   StorePath,
 };
 
 #[derive(Deref)]
 /// A Nix database connection.
-pub struct Connection(rusqlite::Connection);
+pub struct Connection {
+  #[deref]
+  inner: rusqlite::Connection,
+  /// The directories of the store, to read derivations from.
+  dirs:  StoreDirs,
+}
 
 type FilterOkFunc<T> = fn(Result<T, rusqlite::Error>) -> Option<T>;
 
@@ -129,20 +141,22 @@ where
   }
 }
 
-/// Connects to the Nix database
-///
-/// and sets some basic settings
-pub fn connect() -> Result<Connection> {
-  const DATABASE_PATH: &str = "/nix/var/nix/db/db.sqlite";
+/// Connects to the Nix database inside the state directory of `dirs`
+/// and sets some basic settings.
+pub fn connect(dirs: &StoreDirs) -> Result<Connection> {
+  let database_path = dirs.database();
 
   let inner = rusqlite::Connection::open_with_flags(
-    DATABASE_PATH,
+    &database_path,
     OpenFlags::SQLITE_OPEN_READ_ONLY // We only run queries, safeguard against corrupting the DB.
       | OpenFlags::SQLITE_OPEN_NO_MUTEX // Part of the default flags, rusqlite takes care of locking anyways.
       | OpenFlags::SQLITE_OPEN_URI,
   )
   .with_context(|| {
-    format!("failed to connect to Nix database at {DATABASE_PATH}")
+    format!(
+      "failed to connect to Nix database at {path}",
+      path = database_path.display(),
+    )
   })?;
 
   // Perform a batched query to set some settings using PRAGMA
@@ -180,29 +194,25 @@ pub fn connect() -> Result<Connection> {
       ",
     )
     .with_context(|| {
-      format!("failed to cache Nix database at {DATABASE_PATH}")
+      format!(
+        "failed to cache Nix database at {path}",
+        path = database_path.display(),
+      )
     })?;
 
-  Ok(Connection(inner))
+  Ok(Connection {
+    inner,
+    dirs: dirs.clone(),
+  })
 }
 
-fn path_to_canonical_string(path: &Path) -> Result<String> {
-  let path = path.canonicalize().with_context(|| {
+fn path_to_string(path: &StorePath) -> Result<&str> {
+  path.to_str().with_context(|| {
     format!(
-      "failed to canonicalize path '{path}'",
-      path = path.display(),
-    )
-  })?;
-
-  let path = path.into_os_string().into_string().map_err(|path| {
-    anyhow!(
       "failed to convert path '{path}' to valid unicode",
-      path = Path::new(&*path).display(), /* TODO: use .display() directly
-                                           * after Rust 1.87.0 in flake. */
+      path = path.display(),
     )
-  })?;
-
-  Ok(path)
+  })
 }
 
 impl Connection {
@@ -212,21 +222,21 @@ impl Connection {
   pub fn execute_row_query_with_path<T, M>(
     &self,
     query: &str,
-    path: &Path,
+    path: &StorePath,
     map: M,
   ) -> Result<impl Iterator<Item = T>>
   where
     T: 'static,
     M: Fn(&Row) -> rusqlite::Result<T>,
   {
-    let path = path_to_canonical_string(path)?;
+    let path = path_to_string(path)?;
     let stmt = self.prepare_cached(query)?;
     QueryIterator::try_new(stmt, [path], map)
   }
 
   /// Gets the total closure size of the given store path by summing up the nar
   /// size of all dependent derivations.
-  pub fn query_closure_size(&self, path: &Path) -> Result<Size> {
+  pub fn query_closure_size(&self, path: &StorePath) -> Result<Size> {
     const QUERY: &str = "
       WITH RECURSIVE
         graph(p) AS (
@@ -241,7 +251,7 @@ impl Connection {
       JOIN ValidPaths ON p = id;
     ";
 
-    let path = path_to_canonical_string(path)?;
+    let path = path_to_string(path)?;
 
     let closure_size = self
       .prepare_cached(QUERY)?
@@ -253,9 +263,9 @@ impl Connection {
   /// Gets the derivations that are directly included in the system derivation.
   ///
   /// Will not work on non-system derivations.
-  pub fn query_system_derivations(
+  pub(crate) fn query_system_derivations(
     &self,
-    system: &Path,
+    system: &StorePath,
   ) -> Result<impl Iterator<Item = (DerivationId, StorePath)>> {
     const QUERY: &str = "
       WITH
@@ -286,9 +296,9 @@ impl Connection {
   }
 
   /// Gathers all derivations that the given profile path depends on.
-  pub fn query_dependents(
+  pub(crate) fn query_dependents(
     &self,
-    path: &Path,
+    path: &StorePath,
   ) -> Result<impl Iterator<Item = (DerivationId, StorePath)>> {
     const QUERY: &str = "
       WITH RECURSIVE
@@ -312,12 +322,43 @@ impl Connection {
     })
   }
 
+  /// Gathers the information about all derivations that the given profile
+  /// path depends on, except for their references.
+  pub(crate) fn query_dependent_infos(
+    &self,
+    path: &StorePath,
+  ) -> Result<impl Iterator<Item = (DerivationId, PathInfo)>> {
+    const QUERY: &str = "
+      WITH RECURSIVE
+        graph(p) AS (
+          SELECT id
+          FROM ValidPaths
+          WHERE path = ?
+        UNION
+          SELECT reference FROM Refs
+          JOIN graph ON referrer = p
+        )
+      SELECT id, path, narSize, deriver from graph
+      JOIN ValidPaths ON id = p;
+    ";
+
+    self.execute_row_query_with_path(QUERY, path, |row| {
+      Ok((DerivationId(row.get(0)?), PathInfo {
+        path:       StorePath(row.get::<_, String>(1)?.into()),
+        references: Vec::new(),
+        nar_size:   Size::from_bytes(row.get::<_, i64>(2)?),
+        deriver:    row
+          .get::<_, Option<String>>(3)?
+          .map(|deriver| StorePath(deriver.into())),
+      }))
+    })
+  }
+
   /// Returns all edges of the dependency graph.
   ///
   /// You might want to build an adjacency list from the resulting
   /// edges.
-  #[expect(dead_code)]
-  pub fn query_dependency_graph(
+  pub(crate) fn query_dependency_graph(
     &self,
     path: &StorePath,
   ) -> Result<impl Iterator<Item = (DerivationId, DerivationId)>> {
@@ -340,3 +381,333 @@ impl Connection {
     })
   }
 }
+
+impl ClosureSource for Connection {
+  fn query_path_info(&self, path: &StorePath) -> Result<PathInfo> {
+    const INFO_QUERY: &str = "
+      SELECT narSize, deriver FROM ValidPaths
+      WHERE path = ?;
+    ";
+    const REFERENCES_QUERY: &str = "
+      SELECT vp.path FROM ValidPaths
+      JOIN Refs ON referrer = ValidPaths.id
+      JOIN ValidPaths vp ON reference = vp.id
+      WHERE ValidPaths.path = ?;
+    ";
+
+    let (nar_size, deriver) = self
+      .prepare_cached(INFO_QUERY)?
+      .query_row([path_to_string(path)?], |row| {
+        Ok((
+          Size::from_bytes(row.get::<_, i64>(0)?),
+          row
+            .get::<_, Option<String>>(1)?
+            .map(|deriver| StorePath(deriver.into())),
+        ))
+      })
+      .with_context(|| {
+        format!(
+          "path '{path}' is not valid in the Nix database",
+          path = path.display(),
+        )
+      })?;
+
+    let references = self
+      .execute_row_query_with_path(REFERENCES_QUERY, path, |row| {
+        Ok(StorePath(row.get::<_, String>(0)?.into()))
+      })?
+      .collect();
+
+    Ok(PathInfo {
+      path: path.clone(),
+      references,
+      nar_size,
+      deriver,
+    })
+  }
+
+  fn query_closure_infos(&self, path: &StorePath) -> Result<Vec<PathInfo>> {
+    let mut infos = Vec::new();
+    let mut indices = HashMap::new();
+
+    for (id, info) in self.query_dependent_infos(path)? {
+      indices.insert(id, infos.len());
+      infos.push(info);
+    }
+
+    for (referrer, reference) in self.query_dependency_graph(path)? {
+      let (Some(&referrer), Some(&reference)) =
+        (indices.get(&referrer), indices.get(&reference))
+      else {
+        continue;
+      };
+
+      let reference = infos[reference].path.clone();
+      infos[referrer].references.push(reference);
+    }
+
+    Ok(infos)
+  }
+
+  fn query_closure(&self, path: &StorePath) -> Result<Vec<StorePath>> {
+    Ok(self.query_dependents(path)?.map(|(_, path)| path).collect())
+  }
+
+  fn query_closure_nar_sizes(
+    &self,
+    path: &StorePath,
+  ) -> Result<Vec<(StorePath, Size)>> {
+    const QUERY: &str = "
+      WITH RECURSIVE
+        graph(p) AS (
+          SELECT id
+          FROM ValidPaths
+          WHERE path = ?
+        UNION
+          SELECT reference FROM Refs
+          JOIN graph ON referrer = p
+        )
+      SELECT path, narSize from graph
+      JOIN ValidPaths ON id = p;
+    ";
+
+    Ok(
+      self
+        .execute_row_query_with_path(QUERY, path, |row| {
+          Ok((
+            StorePath(row.get::<_, String>(0)?.into()),
+            Size::from_bytes(row.get::<_, i64>(1)?),
+          ))
+        })?
+        .collect(),
+    )
+  }
+
+  fn query_closure_size(&self, path: &StorePath) -> Result<Size> {
+    Connection::query_closure_size(self, path)
+  }
+
+  fn query_system_derivations(
+    &self,
+    system: &StorePath,
+  ) -> Result<Vec<StorePath>> {
+    Ok(
+      Connection::query_system_derivations(self, system)?
+        .map(|(_, path)| path)
+        .collect(),
+    )
+  }
+
+  fn query_derivation_outputs(
+    &self,
+    path: &StorePath,
+  ) -> Result<BTreeMap<String, Option<StorePath>>> {
+    const OUTPUTS_QUERY: &str = "
+      SELECT DerivationOutputs.id, DerivationOutputs.path, output.id IS NOT \
+                                 NULL
+      FROM DerivationOutputs
+      JOIN ValidPaths drv ON drv.id = DerivationOutputs.drv
+      LEFT JOIN ValidPaths output ON output.path = DerivationOutputs.path
+      WHERE drv.path = ?;
+    ";
+    const REALISATION_QUERY: &str = "
+      SELECT ValidPaths.path FROM Realisations
+      JOIN ValidPaths ON ValidPaths.id = Realisations.outputPath
+      WHERE drvPath = ? AND outputName = ?;
+    ";
+
+    // Outputs with a path known in advance are registered along with the
+    // derivation, so the database alone knows them even if the `.drv` file is
+    // gone.
+    let outputs = self
+      .execute_row_query_with_path(OUTPUTS_QUERY, path, |row| {
+        Ok((
+          row.get::<_, String>(0)?,
+          row
+            .get::<_, bool>(2)?
+            .then(|| row.get::<_, String>(1))
+            .transpose()?
+            .map(|output| StorePath(output.into())),
+        ))
+      })?
+      .collect::<BTreeMap<_, _>>();
+
+    if !outputs.is_empty() {
+      return Ok(outputs);
+    }
+
+    // Outputs of content addressed derivations are only known once built, and
+    // recorded as realisations identified by the hash of the derivation. The
+    // table only exists if the feature was ever enabled.
+    let hashes = Derivation::output_hashes(path, &self.dirs)?;
+
+    let mut outputs = hashes
+      .keys()
+      .map(|name| (name.clone(), None))
+      .collect::<BTreeMap<_, _>>();
+
+    for (name, output) in &mut outputs {
+      let hash = &hashes[name];
+
+      let realisation =
+        self
+          .prepare_cached(REALISATION_QUERY)
+          .and_then(|mut query| {
+            query.query_row([hash, name], |row| row.get::<_, String>(0))
+          });
+
+      match realisation {
+        Ok(realisation) => *output = Some(StorePath(realisation.into())),
+        Err(error) => {
+          log::debug!(
+            "found no realisation of output '{name}' of '{path}': {error}",
+            path = path.display(),
+          );
+        },
+      }
+    }
+
+    Ok(outputs)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::{
+    collections::BTreeMap,
+    fs,
+    path::{
+      Path,
+      PathBuf,
+    },
+  };
+
+  use super::connect;
+  use crate::{
+    ClosureSource as _,
+    StoreDirs,
+    StorePath,
+  };
+
+  /// A content addressed derivation without inputs, whose output is
+  /// registered under `sha256:612a...` in the `Realisations` table.
+  const GREETING: &str = r#"Derive([("out","","r:sha256","")],[],[],"x86_64-linux","/bin/sh",["-c","echo hello > $out"],[("builder","/bin/sh"),("name","greeting"),("out","/1rz4g4znpzjwh1xymhjpm42vipw92pr73vdgl6xs1hycac8kf2n9"),("system","x86_64-linux")])"#;
+
+  /// Creates a store in `root` whose database has the tables the derivation
+  /// queries need, with `paths` valid.
+  fn fixture_store(
+    root: &Path,
+    paths: &[&str],
+  ) -> (StoreDirs, rusqlite::Connection) {
+    let dirs = StoreDirs {
+      store: root.join("store"),
+      state: root.join("state"),
+      ..StoreDirs::default()
+    };
+    fs::create_dir_all(&dirs.store).unwrap();
+    fs::create_dir_all(dirs.database().parent().unwrap()).unwrap();
+
+    let database = rusqlite::Connection::open(dirs.database()).unwrap();
+    database
+      .execute_batch(
+        "
+          CREATE TABLE ValidPaths (id INTEGER PRIMARY KEY, path TEXT UNIQUE);
+          CREATE TABLE DerivationOutputs (drv INTEGER, id TEXT, path TEXT);
+          CREATE TABLE Realisations (
+            drvPath TEXT, outputName TEXT, outputPath INTEGER
+          );
+        ",
+      )
+      .unwrap();
+
+    for path in paths {
+      database
+        .execute("INSERT INTO ValidPaths (path) VALUES (?)", [dirs
+          .store
+          .join(path)
+          .to_str()
+          .unwrap()])
+        .unwrap();
+    }
+
+    (dirs, database)
+  }
+
+  #[test]
+  fn input_addressed_outputs() {
+    let root = tempfile::tempdir().unwrap();
+    let (dirs, database) = fixture_store(root.path(), &[
+      "0004yybkm5hnwjyxv129js3mjp7kbrax-hello-2.12.1.drv",
+      "1114yybkm5hnwjyxv129js3mjp7kbrax-hello-2.12.1",
+    ]);
+    let store_path = |name: &str| {
+      StorePath::try_from_in(dirs.store.join(name), &dirs.store).unwrap()
+    };
+
+    for (name, path) in [
+      ("out", "1114yybkm5hnwjyxv129js3mjp7kbrax-hello-2.12.1"),
+      ("man", "1114yybkm5hnwjyxv129js3mjp7kbrax-hello-2.12.1-man"),
+    ] {
+      database
+        .execute("INSERT INTO DerivationOutputs VALUES (1, ?, ?)", [
+          name,
+          dirs.store.join(path).to_str().unwrap(),
+        ])
+        .unwrap();
+    }
+    drop(database);
+
+    // The `.drv` file is not needed, the database knows every output.
+    assert_eq!(
+      connect(&dirs)
+        .unwrap()
+        .query_derivation_outputs(&store_path(
+          "0004yybkm5hnwjyxv129js3mjp7kbrax-hello-2.12.1.drv"
+        ))
+        .unwrap(),
+      BTreeMap::from([
+        ("man".to_owned(), None),
+        (
+          "out".to_owned(),
+          Some(store_path("1114yybkm5hnwjyxv129js3mjp7kbrax-hello-2.12.1")),
+        ),
+      ]),
+      "only outputs in ValidPaths should be built",
+    );
+  }
+
+  #[test]
+  fn content_addressed_outputs() {
+    let root = tempfile::tempdir().unwrap();
+    let (dirs, database) = fixture_store(root.path(), &[
+      "z5jm7llmd8gvrqkhzvd88yavsry7ry1y-greeting.drv",
+      "2224yybkm5hnwjyxv129js3mjp7kbrax-greeting",
+    ]);
+    let greeting_drv = dirs
+      .store
+      .join("z5jm7llmd8gvrqkhzvd88yavsry7ry1y-greeting.drv");
+    let greeting = dirs.store.join("2224yybkm5hnwjyxv129js3mjp7kbrax-greeting");
+
+    fs::write(&greeting_drv, GREETING).unwrap();
+    database
+      .execute(
+        "INSERT INTO Realisations VALUES (?, 'out', 2)",
+        ["sha256:612a1f6202b6bc450faed68da5178c13010ccf434ec07bf3f20575ee22a0e101"],
+      )
+      .unwrap();
+    drop(database);
+
+    let store_path = |path: &PathBuf| {
+      StorePath::try_from_in(path.clone(), &dirs.store).unwrap()
+    };
+
+    assert_eq!(
+      connect(&dirs)
+        .unwrap()
+        .query_derivation_outputs(&store_path(&greeting_drv))
+        .unwrap(),
+      BTreeMap::from([("out".to_owned(), Some(store_path(&greeting)))]),
+      "content addressed outputs should be found by their realisation",
+    );
+  }
+}
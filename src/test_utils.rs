@@ -0,0 +1,19 @@
+//! Fixtures shared between the tests of the modules.
+
+use std::path::PathBuf;
+
+use crate::StorePath;
+
+/// Returns the path named `name` in `/nix/store`, with a hash starting with
+/// `hash` so that paths with the same name can be told apart.
+pub fn store_path_with_hash(hash: char, name: &str) -> StorePath {
+  StorePath::try_from(PathBuf::from(format!(
+    "/nix/store/{hash}{hash}{hash}4yybkm5hnwjyxv129js3mjp7kbrax-{name}"
+  )))
+  .unwrap()
+}
+
+/// Returns the path named `name` in `/nix/store`.
+pub fn store_path(name: &str) -> StorePath {
+  store_path_with_hash('0', name)
+}
@@ -6,10 +6,23 @@ use derive_more::{
   Display,
   From,
 };
+use serde::Serialize;
 
-#[derive(Deref, DerefMut, Display, Debug, Clone, PartialEq, Eq, From)]
+#[derive(
+  Deref, DerefMut, Display, Debug, Clone, PartialEq, Eq, From, Serialize,
+)]
+#[serde(transparent)]
 pub struct Version(String);
 
+impl Version {
+  /// Iterates over the components of the version and the separators between
+  /// them, which are yielded as `Err`.
+  #[must_use]
+  pub fn iter(&self) -> VersionComponentIter<'_> {
+    VersionComponentIter::from(&***self)
+  }
+}
+
 impl PartialOrd for Version {
   fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
     Some(self.cmp(other))
@@ -31,7 +44,7 @@ impl<'a> IntoIterator for &'a Version {
   type IntoIter = VersionComponentIter<'a>;
 
   fn into_iter(self) -> Self::IntoIter {
-    VersionComponentIter::from(&***self)
+    self.iter()
   }
 }
 
@@ -82,7 +95,7 @@ impl cmp::Ord for VersionComponent<'_> {
   }
 }
 
-/// Yields [`VertionComponent`] from a version string.
+/// Yields [`VersionComponent`] from a version string.
 #[derive(Deref, DerefMut, From)]
 pub struct VersionComponentIter<'a>(&'a str);
 